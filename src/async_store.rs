@@ -0,0 +1,85 @@
+use crate::{
+    error::{CaskaError, Result},
+    options::Options,
+    store::Store,
+    ToBytes,
+};
+use std::{io, path::Path, sync::Arc};
+use tokio::{sync::RwLock, task};
+
+/// An async handle to a [`Store`]. File I/O runs on tokio's blocking pool so
+/// callers never stall the executor on disk access.
+#[derive(Debug, Clone)]
+pub(crate) struct AsyncStore {
+    inner: Arc<RwLock<Store>>,
+}
+
+impl AsyncStore {
+    pub(crate) async fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(dir, Options::default()).await
+    }
+
+    pub(crate) async fn open_with(dir: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let store = blocking(move || Store::open_with(dir, options)).await?;
+        Ok(Self {
+            inner: Arc::new(RwLock::new(store)),
+        })
+    }
+
+    pub(crate) async fn get<K: ToBytes>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        let key = key.to_bytes();
+        let inner = self.inner.clone();
+        blocking(move || inner.blocking_read().get(key)).await
+    }
+
+    pub(crate) async fn put<K: ToBytes, V: ToBytes>(&self, key: K, value: V) -> Result<()> {
+        let (key, value) = (key.to_bytes(), value.to_bytes());
+        let inner = self.inner.clone();
+        blocking(move || inner.blocking_write().put(key, value)).await
+    }
+
+    pub(crate) async fn delete<K: ToBytes>(&self, key: K) -> Result<()> {
+        let key = key.to_bytes();
+        let inner = self.inner.clone();
+        blocking(move || inner.blocking_write().delete(key)).await
+    }
+}
+
+async fn blocking<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    task::spawn_blocking(f)
+        .await
+        .map_err(|err| CaskaError::Io(io::Error::other(err)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn async_store_can_put_get_and_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = AsyncStore::open(dir.path()).await.unwrap();
+
+        store.put("hello", "world").await.unwrap();
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").await.unwrap());
+
+        store.delete("hello").await.unwrap();
+        assert_eq!(None, store.get("hello").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn async_store_persists_across_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = AsyncStore::open(dir.path()).await.unwrap();
+        store.put("hello", "world").await.unwrap();
+        drop(store);
+
+        let store = AsyncStore::open(dir.path()).await.unwrap();
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").await.unwrap());
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// Target false positive rate used to size the filter.
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A fixed-size Bloom filter over key bytes. A miss means the key was never
+/// inserted; a hit only means it might have been.
+#[derive(Debug, Clone)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub(crate) fn new(expected_keys: usize) -> Self {
+        let n = expected_keys.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_indexes(key) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    pub(crate) fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_indexes(key)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bits.fill(0);
+    }
+
+    /// Derives `num_hashes` bit positions from two base hashes.
+    fn bit_indexes(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let h1 = hash(key, 0);
+        let h2 = hash(key, 1) | 1;
+        let num_bits = self.num_bits;
+        (0..u64::from(self.num_hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+fn hash(key: &[u8], seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter_never_reports_inserted_keys_as_absent() {
+        let mut bloom = BloomFilter::new(1000);
+        for i in 0..1000u32 {
+            bloom.insert(&i.to_be_bytes());
+        }
+
+        assert!((0..1000u32).all(|i| bloom.may_contain(&i.to_be_bytes())));
+
+        let false_positives = (1000..11000u32)
+            .filter(|i| bloom.may_contain(&i.to_be_bytes()))
+            .count();
+        assert!(false_positives < 500, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn cleared_bloom_filter_contains_nothing() {
+        let mut bloom = BloomFilter::new(10);
+        bloom.insert(b"hello");
+
+        bloom.clear();
+
+        assert!(!bloom.may_contain(b"hello"));
+    }
+}
@@ -0,0 +1,67 @@
+use std::mem;
+
+/// Encoded entries waiting to be written to a data file in one go.
+#[derive(Debug, Default)]
+pub(crate) struct WriteBuffer {
+    data: Vec<u8>,
+    /// Bytes to collect before a flush is due; 0 flushes every write.
+    capacity: usize,
+}
+
+impl WriteBuffer {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.data.len() >= self.capacity
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// The `len` bytes at `offset`, counted from the start of the buffer.
+    pub(crate) fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.data.get(offset..offset + len)
+    }
+
+    /// Empties the buffer, handing back what it held.
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        mem::replace(&mut self.data, Vec::with_capacity(self.capacity))
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.data.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_buffer_fills_up_and_drains() {
+        let mut buffer = WriteBuffer::new(8);
+        buffer.push(b"hello");
+        assert!(!buffer.is_full());
+        assert_eq!(Some(b"ell".as_slice()), buffer.read(1, 3));
+        assert_eq!(None, buffer.read(4, 3));
+
+        buffer.push(b"world");
+        assert!(buffer.is_full());
+        assert_eq!(b"helloworld".to_vec(), buffer.take());
+        assert!(buffer.is_empty());
+    }
+}
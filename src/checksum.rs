@@ -0,0 +1,94 @@
+use xxhash_rust::xxh64::Xxh64;
+
+/// Algorithm used for per-entry checksums, recorded in each file header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ChecksumKind {
+    #[default]
+    Crc32,
+    /// Faster on large values. The 64-bit digest is folded into the same
+    /// 32-bit slot as CRC32 so both share one entry layout.
+    XxHash64,
+}
+
+impl ChecksumKind {
+    /// Tag written into file headers.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::Crc32 => 0,
+            Self::XxHash64 => 1,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Crc32),
+            1 => Some(Self::XxHash64),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            Self::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            Self::XxHash64 => Hasher::XxHash64(Xxh64::new(0)),
+        }
+    }
+
+    pub(crate) fn checksum(self, data: &[u8]) -> u32 {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+/// Incrementally computes a checksum of either kind.
+pub(crate) enum Hasher {
+    Crc32(crc32fast::Hasher),
+    XxHash64(Xxh64),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Crc32(hasher) => hasher.update(data),
+            Self::XxHash64(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> u32 {
+        match self {
+            Self::Crc32(hasher) => hasher.finalize(),
+            Self::XxHash64(hasher) => {
+                let digest = hasher.digest();
+                (digest ^ (digest >> 32)) as u32
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_tags_round_trip() {
+        for kind in [ChecksumKind::Crc32, ChecksumKind::XxHash64] {
+            assert_eq!(Some(kind), ChecksumKind::from_tag(kind.tag()));
+        }
+        assert_eq!(None, ChecksumKind::from_tag(2));
+    }
+
+    #[test]
+    fn incremental_and_one_shot_checksums_agree() {
+        for kind in [ChecksumKind::Crc32, ChecksumKind::XxHash64] {
+            let mut hasher = kind.hasher();
+            hasher.update(b"hello ");
+            hasher.update(b"world");
+            assert_eq!(kind.checksum(b"hello world"), hasher.finalize());
+        }
+        assert_eq!(
+            crc32fast::hash(b"hello"),
+            ChecksumKind::Crc32.checksum(b"hello")
+        );
+    }
+}
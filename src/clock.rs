@@ -0,0 +1,50 @@
+use std::{
+    fmt::Debug,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Source of entry timestamps, in seconds since the Unix epoch.
+pub(crate) trait Clock: Debug + Send + Sync {
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+#[cfg(test)]
+pub(crate) use mock::MockClock;
+
+#[cfg(test)]
+mod mock {
+    use super::Clock;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// A clock that only moves when told to.
+    #[derive(Debug)]
+    pub(crate) struct MockClock(AtomicU64);
+
+    impl MockClock {
+        pub(crate) fn new(now: u64) -> Self {
+            Self(AtomicU64::new(now))
+        }
+
+        pub(crate) fn advance(&self, secs: u64) {
+            self.0.fetch_add(secs, Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+}
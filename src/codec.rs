@@ -0,0 +1,112 @@
+use crate::{
+    error::{CaskaError, Result},
+    MemoryStore, ToBytes,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Format `put_serde` stores values in. Reads must use the same one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum SerdeCodec {
+    /// Compact, but only readable by bincode.
+    #[default]
+    Bincode,
+    Json,
+}
+
+impl SerdeCodec {
+    fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Bincode => bincode::serde::encode_to_vec(value, bincode::config::standard())
+                .map_err(|err| CaskaError::Serialize(err.to_string())),
+            Self::Json => {
+                serde_json::to_vec(value).map_err(|err| CaskaError::Serialize(err.to_string()))
+            }
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Self::Bincode => bincode::serde::decode_from_slice(bytes, bincode::config::standard())
+                .map(|(value, _)| value)
+                .map_err(|err| CaskaError::Deserialize(err.to_string())),
+            Self::Json => serde_json::from_slice(bytes)
+                .map_err(|err| CaskaError::Deserialize(err.to_string())),
+        }
+    }
+}
+
+impl MemoryStore {
+    /// Stores `value` encoded with the configured [`SerdeCodec`].
+    pub(crate) fn put_serde<K: ToBytes, T: Serialize>(&mut self, key: K, value: &T) -> Result<()> {
+        let bytes = self.options.serde_codec.encode(value)?;
+        self.put(key, bytes)
+    }
+
+    pub(crate) fn get_serde<K: ToBytes, T: DeserializeOwned>(&self, key: K) -> Result<Option<T>> {
+        let Some(entry) = self.lookup(&key.to_bytes()) else {
+            return Ok(None);
+        };
+        self.options
+            .serde_codec
+            .decode(&self.try_value(entry)?)
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        street: String,
+        zip: Option<u32>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Person {
+        name: String,
+        age: u8,
+        addresses: Vec<Address>,
+    }
+
+    fn person() -> Person {
+        Person {
+            name: "john".into(),
+            age: 42,
+            addresses: vec![
+                Address {
+                    street: "1 Main St".into(),
+                    zip: Some(12345),
+                },
+                Address {
+                    street: "2 High St".into(),
+                    zip: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn serde_values_round_trip_with_either_codec() {
+        for codec in [SerdeCodec::Bincode, SerdeCodec::Json] {
+            let mut store = MemoryStore::with_options(Options::new().serde_codec(codec));
+            store.put_serde("john", &person()).unwrap();
+
+            assert_eq!(Some(person()), store.get_serde("john").unwrap());
+            assert_eq!(None, store.get_serde::<_, Person>("jane").unwrap());
+        }
+    }
+
+    #[test]
+    fn get_serde_reports_values_of_the_wrong_shape() {
+        let options = Options::new().serde_codec(SerdeCodec::Json);
+        let mut store = MemoryStore::with_options(options);
+        store.put("john", "not json").unwrap();
+
+        let err = store.get_serde::<_, Person>("john").unwrap_err();
+        assert!(matches!(err, CaskaError::Deserialize(_)));
+    }
+}
@@ -0,0 +1,74 @@
+use crate::error::{CaskaError, Result};
+use std::borrow::Cow;
+
+/// Entry flag bits recording how a value was compressed.
+pub(crate) const FLAG_LZ4: u8 = 0b01;
+pub(crate) const FLAG_ZSTD: u8 = 0b10;
+const COMPRESSION_FLAGS: u8 = FLAG_LZ4 | FLAG_ZSTD;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl Compression {
+    /// Compresses `value`, returning the flag to store alongside it. Values
+    /// that don't shrink are kept as they are.
+    pub(crate) fn compress(self, value: Vec<u8>) -> Result<(u8, Vec<u8>)> {
+        let (flag, compressed) = match self {
+            Self::None => return Ok((0, value)),
+            Self::Lz4 => (FLAG_LZ4, lz4_flex::compress_prepend_size(&value)),
+            Self::Zstd => (FLAG_ZSTD, zstd::encode_all(value.as_slice(), ZSTD_LEVEL)?),
+        };
+
+        if compressed.len() < value.len() {
+            Ok((flag, compressed))
+        } else {
+            Ok((0, value))
+        }
+    }
+}
+
+/// Restores a stored value according to its entry flags.
+pub(crate) fn decompress(flags: u8, value: &[u8]) -> Result<Cow<'_, [u8]>> {
+    match flags & COMPRESSION_FLAGS {
+        0 => Ok(Cow::Borrowed(value)),
+        FLAG_LZ4 => lz4_flex::decompress_size_prepended(value)
+            .map(Cow::Owned)
+            .map_err(|err| CaskaError::Compression(err.to_string())),
+        FLAG_ZSTD => zstd::decode_all(value)
+            .map(Cow::Owned)
+            .map_err(|err| CaskaError::Compression(err.to_string())),
+        flags => Err(CaskaError::Compression(format!(
+            "unknown compression flags {flags:#04b}"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_values_round_trip() {
+        let value = b"hello ".repeat(100);
+
+        for compression in [Compression::None, Compression::Lz4, Compression::Zstd] {
+            let (flags, stored) = compression.compress(value.clone()).unwrap();
+            assert_eq!(value, decompress(flags, &stored).unwrap().as_ref());
+        }
+    }
+
+    #[test]
+    fn incompressible_values_are_stored_raw() {
+        let (flags, stored) = Compression::Zstd.compress(b"hi".to_vec()).unwrap();
+
+        assert_eq!(0, flags);
+        assert_eq!(b"hi".to_vec(), stored);
+    }
+}
@@ -0,0 +1,1313 @@
+use crate::{
+    buffer::WriteBuffer,
+    checksum::ChecksumKind,
+    compression,
+    endian::Endianness,
+    error::{CaskaError, Result},
+    event::StoreEvent,
+    index_keydir, load_hint, load_keydir,
+    options::{Options, RecoveryMode, SyncMode},
+    retained_tombstones, sync_parent_dir, write_atomically, write_hint, File, FileHeader, KeyDir,
+    KeyDirEntry, KeyValueEntry, MergeReport, RecoveryInfo, ToBytes,
+};
+use memmap2::Mmap;
+use std::{
+    borrow::Cow,
+    fs::{self, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    iter,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+const FILE_ID: u32 = 0;
+const MERGE_EXTENSION: &str = "merge";
+
+/// An append-only data file along with its write buffer and sync bookkeeping.
+#[derive(Debug)]
+pub(crate) struct DataFile {
+    file: fs::File,
+    /// Bytes written to the file itself, not counting `buffer`.
+    size: u64,
+    byte_order: Endianness,
+    /// Taken from the header, so appends match whatever the file was created
+    /// with.
+    checksum: ChecksumKind,
+    /// Maps the whole file when `use_mmap` is set, refreshed after every
+    /// write so reads can slice values straight out of it.
+    mmap: Option<Mmap>,
+    use_mmap: bool,
+    verify_on_read: bool,
+    buffer: WriteBuffer,
+    unsynced_writes: usize,
+    sync_count: usize,
+}
+
+impl DataFile {
+    /// Opens or creates the file at `path`, writing a header if it's new.
+    pub(crate) fn open(path: &Path, options: &Options) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            let header = FileHeader::new(options.byte_order, options.checksum);
+            file.write_all(&header.encode())?;
+            file.rewind()?;
+        }
+        Self::from_file(file, options)
+    }
+
+    /// Opens an existing file without write access.
+    pub(crate) fn open_read_only(path: &Path, options: &Options) -> Result<Self> {
+        Self::from_file(fs::File::open(path)?, options)
+    }
+
+    fn from_file(mut file: fs::File, options: &Options) -> Result<Self> {
+        let checksum = read_header(&mut file)?.checksum;
+        let mut data_file = Self {
+            size: file.metadata()?.len(),
+            file,
+            byte_order: options.byte_order,
+            checksum,
+            mmap: None,
+            use_mmap: options.use_mmap,
+            verify_on_read: options.verify_on_read,
+            buffer: WriteBuffer::new(options.write_buffer_size),
+            unsynced_writes: 0,
+            sync_count: 0,
+        };
+        data_file.remap()?;
+        Ok(data_file)
+    }
+
+    fn remap(&mut self) -> Result<()> {
+        if self.use_mmap {
+            // SAFETY: this handle drops the mapping before it shrinks the file
+            // and remaps after every change, so its own writes never leave
+            // the mapping past the end of the file. Nothing stops another
+            // handle from truncating the file underneath it: a writable
+            // `Store` holds the lock file, but read-only stores and
+            // `DiskStore` take no lock, so callers must not shrink a file
+            // some other handle has mapped.
+            self.mmap = Some(unsafe { Mmap::map(&self.file)? });
+        }
+        Ok(())
+    }
+
+    /// Size including buffered writes, which is where the next entry lands.
+    pub(crate) fn size(&self) -> u64 {
+        self.size + self.buffer.len() as u64
+    }
+
+    /// Validates the header without reading any entries.
+    fn check_header(&mut self) -> Result<()> {
+        read_header(&mut self.file)?.check_byte_order(self.byte_order)?;
+        Ok(())
+    }
+
+    /// Encodes `entry` the way this file's header says entries are stored.
+    pub(crate) fn encode(&self, entry: KeyValueEntry) -> Result<Vec<u8>> {
+        entry.encode(self.byte_order, self.checksum)
+    }
+
+    pub(crate) fn read_all(&mut self) -> Result<Vec<u8>> {
+        self.flush()?;
+        let mut data = vec![];
+        self.file.rewind()?;
+        self.file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// An in-memory copy of the whole file, for scanning its entries.
+    pub(crate) fn snapshot(&mut self, id: u32) -> Result<File> {
+        Ok(File {
+            id,
+            byte_order: self.byte_order,
+            checksum: self.checksum,
+            data: self.read_all()?,
+        })
+    }
+
+    /// A header for a new file laid out like this one.
+    pub(crate) fn header(&self) -> FileHeader {
+        FileHeader::new(self.byte_order, self.checksum)
+    }
+
+    /// Drops a partially written entry so new appends start on a clean boundary.
+    pub(crate) fn truncate(&mut self, size: u64) -> Result<()> {
+        self.mmap = None;
+        self.file.set_len(size)?;
+        self.size = size;
+        self.remap()
+    }
+
+    pub(crate) fn read_value(&self, key: &[u8], entry: &KeyDirEntry) -> Result<Vec<u8>> {
+        self.read_value_cow(key, entry).map(Cow::into_owned)
+    }
+
+    /// Like `read_value`, but borrows uncompressed values from the write
+    /// buffer or memory map instead of copying them. With `verify_on_read`
+    /// the whole entry is read so its checksum can be checked first.
+    pub(crate) fn read_value_cow(&self, key: &[u8], entry: &KeyDirEntry) -> Result<Cow<'_, [u8]>> {
+        let value_pos = u64::from(entry.value_pos);
+        let value_sz = entry.value_sz as usize;
+        if !self.verify_on_read {
+            return decompress_tail(entry.flags, self.read_range(value_pos, value_sz)?, value_sz);
+        }
+
+        let entry_pos = value_pos - (KeyValueEntry::HEADER_SIZE + key.len()) as u64;
+        let encoded = self.read_range(entry_pos, entry.encoded_size(key))?;
+        KeyValueEntry::verify_checksum(&encoded, self.byte_order, self.checksum)?;
+        decompress_tail(entry.flags, encoded, value_sz)
+    }
+
+    /// Indexes the flushed part of the file from entry headers and keys.
+    pub(crate) fn index(&self, file_id: u32, now: u64) -> Result<(KeyDir, RecoveryInfo)> {
+        let mut file = &self.file;
+        let index = index_keydir(file_id, &mut file, self.size, self.byte_order, now)?;
+        if index.1.bytes_truncated == 0 {
+            return Ok(index);
+        }
+
+        // Without checksums, a corrupt length mid-file reads like a torn
+        // tail. Before anything gets cut, scan the file the way a full open
+        // does, which fails on corruption and only reports a real tail.
+        let data = self.read_range(0, self.size as usize)?;
+        load_keydir(file_id, &data, self.byte_order, now)
+    }
+
+    /// Every flushed entry, oldest first, read from its header and key with
+    /// the value left out. Stops at a torn tail or after the first error.
+    pub(crate) fn entry_headers(&self) -> impl Iterator<Item = Result<KeyValueEntry>> + '_ {
+        let mut file = &self.file;
+        let mut entry_pos = FileHeader::SIZE as u64;
+        iter::from_fn(move || {
+            if entry_pos >= self.size {
+                return None;
+            }
+            let entry = file
+                .seek(SeekFrom::Start(entry_pos))
+                .map_err(CaskaError::from)
+                .and_then(|_| KeyValueEntry::read_indexed(&mut file, self.byte_order));
+            match entry {
+                Ok(entry) if entry_pos + entry.encoded_size() as u64 <= self.size => {
+                    entry_pos += entry.encoded_size() as u64;
+                    Some(Ok(entry))
+                }
+                Ok(_) | Err(CaskaError::Truncated) => None,
+                Err(err) => {
+                    entry_pos = self.size;
+                    Some(Err(err))
+                }
+            }
+        })
+    }
+
+    /// The value bytes `entry` points at, exactly as stored.
+    pub(crate) fn read_stored(&self, entry: &KeyDirEntry) -> Result<Vec<u8>> {
+        let stored = self.read_range(u64::from(entry.value_pos), entry.value_sz as usize)?;
+        Ok(stored.into_owned())
+    }
+
+    /// The `len` bytes at `pos`, borrowed from the write buffer or memory map
+    /// when they're there.
+    fn read_range(&self, pos: u64, len: usize) -> Result<Cow<'_, [u8]>> {
+        if let Some(offset) = pos.checked_sub(self.size) {
+            let data = self.buffer.read(offset as usize, len);
+            return data.map(Cow::Borrowed).ok_or_else(past_the_end);
+        }
+
+        if let Some(mmap) = &self.mmap {
+            let pos = pos as usize;
+            let data = mmap.get(pos..pos + len);
+            return data.map(Cow::Borrowed).ok_or_else(past_the_end);
+        }
+
+        let mut data = vec![0; len];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut data)?;
+        Ok(Cow::Owned(data))
+    }
+
+    pub(crate) fn append(&mut self, data: &[u8], sync_mode: SyncMode) -> Result<()> {
+        self.buffer.push(data);
+        if self.buffer.is_full() {
+            self.flush()?;
+        }
+
+        self.unsynced_writes += 1;
+        let sync_due = match sync_mode {
+            SyncMode::Never => false,
+            SyncMode::Always => true,
+            SyncMode::EveryN(n) => self.unsynced_writes >= n,
+        };
+        if sync_due {
+            self.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes out anything still buffered, without syncing it.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let data = self.buffer.take();
+        self.file.write_all(&data)?;
+        self.size += data.len() as u64;
+        self.remap()
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        self.flush()?;
+        self.file.sync_all()?;
+        self.unsynced_writes = 0;
+        self.sync_count += 1;
+        Ok(())
+    }
+
+    /// Throws away every entry, leaving just the header.
+    fn clear(&mut self) -> Result<()> {
+        let header = self.header().encode();
+        self.buffer.clear();
+        self.mmap = None;
+        self.file.set_len(0)?;
+        self.file.write_all(&header)?;
+        self.size = header.len() as u64;
+        self.remap()
+    }
+}
+
+impl Drop for DataFile {
+    fn drop(&mut self) {
+        // Errors can't be reported from here; call `flush` to see them.
+        let _ = self.flush();
+    }
+}
+
+/// Decompresses the `value_sz` bytes at the end of `stored`, borrowing
+/// whenever `stored` is borrowed and the value isn't compressed.
+fn decompress_tail(flags: u8, stored: Cow<'_, [u8]>, value_sz: usize) -> Result<Cow<'_, [u8]>> {
+    let start = stored.len() - value_sz;
+    match stored {
+        Cow::Borrowed(stored) => compression::decompress(flags, &stored[start..]),
+        Cow::Owned(mut stored) => {
+            if let Cow::Owned(value) = compression::decompress(flags, &stored[start..])? {
+                return Ok(Cow::Owned(value));
+            }
+            stored.drain(..start);
+            Ok(Cow::Owned(stored))
+        }
+    }
+}
+
+fn past_the_end() -> CaskaError {
+    CaskaError::InvalidEntry("value lies past the end of the file".into())
+}
+
+fn read_header(file: &mut fs::File) -> Result<FileHeader> {
+    let mut header = [0; FileHeader::SIZE];
+    file.rewind()?;
+    match file.read_exact(&mut header) {
+        Ok(()) => FileHeader::decode(&header),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(CaskaError::InvalidMagic),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// What `write_merged` left at the merge path.
+struct WrittenMerge {
+    keydir: KeyDir,
+    entries_before: usize,
+    entries_after: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct DiskStore {
+    file: DataFile,
+    path: PathBuf,
+    keydir: KeyDir,
+    hint_path: PathBuf,
+    has_hint: bool,
+    options: Options,
+    recovery: RecoveryInfo,
+    /// Set under `RecoveryMode::Lazy` until the file has been indexed.
+    /// Lookups only have `&self`, hence the lock; the first one fills it in.
+    deferred: Option<Mutex<Option<(KeyDir, RecoveryInfo)>>>,
+    /// Sequence number of the most recent write.
+    seq: u64,
+}
+
+impl DiskStore {
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, Options::default())
+    }
+
+    pub(crate) fn open_with(path: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let path = options.data_dir.join(path);
+        let hint_path = path.with_extension("hint");
+        let mut file = DataFile::open(&path, &options)?;
+
+        // A merge that didn't get as far as the rename leaves its output
+        // behind; the data file itself is still the complete original.
+        let merge_path = path.with_extension(MERGE_EXTENSION);
+        if merge_path.exists() {
+            fs::remove_file(merge_path)?;
+        }
+
+        file.check_header()?;
+        let hint = if hint_path.exists() {
+            // A hint left half-written by a crash is as useless as a stale
+            // one, so both fall back to a full scan.
+            let hint = fs::read(&hint_path)?;
+            let hint = load_hint(FILE_ID, &hint, file.size() as usize, options.byte_order);
+            if !matches!(hint, Ok(Some(_))) {
+                fs::remove_file(&hint_path)?;
+            }
+            hint.ok().flatten()
+        } else {
+            None
+        };
+
+        let has_hint = hint.is_some();
+        let has_entries = file.size() > FileHeader::SIZE as u64;
+        let mut deferred = None;
+        let (keydir, recovery) = if let Some((keydir, last_seq)) = hint {
+            let recovery = RecoveryInfo {
+                last_seq,
+                ..RecoveryInfo::default()
+            };
+            (keydir, recovery)
+        } else if has_entries && options.recovery == RecoveryMode::HintOnly {
+            return Err(CaskaError::HintUnavailable);
+        } else if has_entries && options.recovery == RecoveryMode::Lazy {
+            deferred = Some(Mutex::new(None));
+            (KeyDir::new(), RecoveryInfo::default())
+        } else {
+            let data = file.read_all()?;
+            let (keydir, recovery) =
+                load_keydir(FILE_ID, &data, options.byte_order, options.clock.now())?;
+            if recovery.bytes_truncated > 0 {
+                file.truncate((data.len() - recovery.bytes_truncated) as u64)?;
+                let truncated_bytes = recovery.bytes_truncated;
+                options.emit(|| StoreEvent::Repaired { truncated_bytes });
+            }
+            (keydir, recovery)
+        };
+        let seq = keydir
+            .values()
+            .map(|entry| entry.seq)
+            .max()
+            .unwrap_or(0)
+            .max(recovery.last_seq);
+
+        Ok(Self {
+            file,
+            path,
+            keydir,
+            hint_path,
+            has_hint,
+            options,
+            recovery,
+            deferred,
+            seq,
+        })
+    }
+
+    pub(crate) fn recovery(&self) -> RecoveryInfo {
+        self.recovery
+    }
+
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        self.file.sync()
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+
+    /// Saves a hint for the next `open`, reporting any error that dropping
+    /// the store would have to ignore.
+    pub(crate) fn close(mut self) -> Result<()> {
+        self.file.sync()?;
+        self.write_hint_file()
+    }
+
+    pub(crate) fn write_hint_file(&mut self) -> Result<()> {
+        self.finish_index()?;
+        // The hint would otherwise point at entries that aren't on disk yet.
+        self.file.flush()?;
+        let mut hint = vec![];
+        write_hint(
+            &self.keydir,
+            self.file.size() as usize,
+            self.seq,
+            &mut hint,
+            self.options.byte_order,
+        )?;
+        write_atomically(&self.hint_path, &hint)?;
+        self.has_hint = true;
+
+        Ok(())
+    }
+
+    pub(crate) fn get<K: ToBytes>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        let key = key.to_bytes();
+        match self.lookup(&key)? {
+            Some(entry) => self.file.read_value(&key, &entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Like `get`, but only copies the value when it isn't in memory
+    /// already, i.e. without mmap or when compressed.
+    pub(crate) fn get_cow<K: ToBytes>(&self, key: K) -> Result<Option<Cow<'_, [u8]>>> {
+        let key = key.to_bytes();
+        match self.lookup(&key)? {
+            Some(entry) => self.file.read_value_cow(&key, &entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        self.finish_index()?;
+        let entry =
+            KeyValueEntry::new(self.options.clock.now(), key, value).with_seq(self.next_seq());
+        entry.check_size(&self.options)?;
+        let entry = entry.compress(self.options.compression)?;
+        let keydir_entry = KeyDirEntry::new(FILE_ID, self.file.size() as usize, &entry);
+        let key = entry.key.clone();
+
+        self.append(&self.file.encode(entry)?)?;
+        let value_sz = keydir_entry.value_sz;
+        self.keydir.insert(key.clone(), keydir_entry);
+        self.options.emit(|| StoreEvent::Put { key, value_sz });
+
+        Ok(())
+    }
+
+    /// Writes all pairs with a single append, so the sync policy applies to
+    /// the batch as a whole.
+    pub(crate) fn put_many<K, V, I>(&mut self, pairs: I) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.finish_index()?;
+        let tstamp = self.options.clock.now();
+        let mut batch = vec![];
+        let mut keydir_entries = vec![];
+
+        for (key, value) in pairs {
+            let entry = KeyValueEntry::new(tstamp, key, value).with_seq(self.next_seq());
+            entry.check_size(&self.options)?;
+            let entry = entry.compress(self.options.compression)?;
+            let entry_pos = self.file.size() as usize + batch.len();
+            keydir_entries.push((
+                entry.key.clone(),
+                KeyDirEntry::new(FILE_ID, entry_pos, &entry),
+            ));
+            batch.extend(self.file.encode(entry)?);
+        }
+
+        self.append(&batch)?;
+        for (key, keydir_entry) in keydir_entries {
+            let value_sz = keydir_entry.value_sz;
+            self.keydir.insert(key.clone(), keydir_entry);
+            self.options.emit(|| StoreEvent::Put { key, value_sz });
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn delete<K: ToBytes>(&mut self, key: K) -> Result<()> {
+        self.finish_index()?;
+        let entry =
+            KeyValueEntry::tombstone(self.options.clock.now(), key).with_seq(self.next_seq());
+        entry.check_size(&self.options)?;
+        let key = entry.key.clone();
+
+        self.append(&self.file.encode(entry)?)?;
+        self.keydir.remove(&key);
+        self.options.emit(|| StoreEvent::Delete { key });
+
+        Ok(())
+    }
+
+    /// Drops every key and truncates the data file.
+    pub(crate) fn clear(&mut self) -> Result<()> {
+        self.remove_hint()?;
+        self.file.clear()?;
+        self.keydir.clear();
+        self.deferred = None;
+
+        Ok(())
+    }
+
+    /// Rewrites the data file with only its live entries and recent
+    /// tombstones. The compacted copy is synced to disk beside the original
+    /// and then renamed over it, so a crash at any point leaves one complete
+    /// data file in place.
+    pub(crate) fn merge(&mut self) -> Result<MergeReport> {
+        self.finish_index()?;
+        let started = Instant::now();
+        let bytes_before = self.file.size() as usize;
+        let merged = self.write_merged()?;
+
+        // A hint left behind would describe the old layout.
+        self.remove_hint()?;
+        fs::rename(self.path.with_extension(MERGE_EXTENSION), &self.path)?;
+        sync_parent_dir(&self.path)?;
+        self.file = DataFile::open(&self.path, &self.options)?;
+        self.keydir = merged.keydir;
+
+        let bytes_after = self.file.size() as usize;
+        let reclaimed_bytes = bytes_before.saturating_sub(bytes_after);
+        self.options.emit(|| StoreEvent::Merge { reclaimed_bytes });
+
+        Ok(MergeReport {
+            files_merged: 1,
+            entries_before: merged.entries_before,
+            entries_after: merged.entries_after,
+            bytes_before,
+            bytes_after,
+            reclaimed_bytes,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Writes and syncs the live entries and any tombstones younger than
+    /// `tombstone_ttl` to the merge path, counting entries on the way in
+    /// and out.
+    fn write_merged(&mut self) -> Result<WrittenMerge> {
+        self.file.flush()?;
+        let now = self.options.clock.now();
+        let mut data = self.file.header().encode();
+        let mut keydir = KeyDir::new();
+
+        let mut entries_before = 0;
+        let mut headers = self.file.entry_headers().inspect(|_| entries_before += 1);
+        let tombstones = retained_tombstones(headers.by_ref(), &self.keydir, &self.options, now)?;
+        // Whatever the tombstone scan left, which is everything without a
+        // ttl, still needs counting.
+        for entry in headers {
+            entry?;
+        }
+        let tombstone_count = tombstones.len();
+        for tombstone in tombstones.into_values() {
+            data.extend(self.file.encode(tombstone)?);
+        }
+
+        for (key, keydir_entry) in &self.keydir {
+            if keydir_entry.is_expired(now) {
+                continue;
+            }
+            let value = self.file.read_stored(keydir_entry)?;
+            let entry = KeyValueEntry::restore(key.clone(), keydir_entry, value);
+            keydir.insert(key.clone(), KeyDirEntry::new(FILE_ID, data.len(), &entry));
+            data.extend(self.file.encode(entry)?);
+        }
+
+        let mut merged = fs::File::create(self.path.with_extension(MERGE_EXTENSION))?;
+        merged.write_all(&data)?;
+        merged.sync_all()?;
+
+        let entries_after = keydir.len() + tombstone_count;
+        Ok(WrittenMerge {
+            keydir,
+            entries_before,
+            entries_after,
+        })
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    /// The keydir entry for `key`, indexing the file first if that was
+    /// deferred.
+    fn lookup(&self, key: &[u8]) -> Result<Option<KeyDirEntry>> {
+        let Some(deferred) = &self.deferred else {
+            return Ok(self.keydir.get(key).cloned());
+        };
+        let mut index = deferred.lock().expect("index lock poisoned");
+        if index.is_none() {
+            *index = Some(self.file.index(FILE_ID, self.options.clock.now())?);
+        }
+        Ok(index
+            .as_ref()
+            .and_then(|(keydir, _)| keydir.get(key).cloned()))
+    }
+
+    /// Installs a deferred index as the keydir, building it if no lookup has
+    /// yet, so writes can go ahead.
+    fn finish_index(&mut self) -> Result<()> {
+        let Some(deferred) = &mut self.deferred else {
+            return Ok(());
+        };
+        let index = deferred.get_mut().expect("index lock poisoned").take();
+        let (keydir, recovery) = match index {
+            Some(index) => index,
+            None => self.file.index(FILE_ID, self.options.clock.now())?,
+        };
+        self.deferred = None;
+
+        if recovery.bytes_truncated > 0 {
+            self.file
+                .truncate(self.file.size() - recovery.bytes_truncated as u64)?;
+            let truncated_bytes = recovery.bytes_truncated;
+            self.options
+                .emit(|| StoreEvent::Repaired { truncated_bytes });
+        }
+        self.keydir = keydir;
+        self.recovery = recovery;
+        self.seq = self.seq.max(recovery.last_seq);
+        Ok(())
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        // Any existing hint no longer describes the data file once it grows.
+        self.remove_hint()?;
+        self.file.append(data, self.options.sync_mode)
+    }
+
+    fn remove_hint(&mut self) -> Result<()> {
+        if self.has_hint {
+            fs::remove_file(&self.hint_path)?;
+            self.has_hint = false;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DiskStore {
+    fn drop(&mut self) {
+        if !self.has_hint {
+            // Errors can't be reported from here; call `close` to see them.
+            let _ = self.write_hint_file();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::MockClock, compression::Compression, testing::append_torn_entry};
+    use std::{sync::Arc, time::Duration};
+
+    #[test]
+    fn disk_store_can_put_and_get_a_key_value_pair() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = DiskStore::open(dir.path().join("caska.data")).unwrap();
+
+        store.put("hello", "world").unwrap();
+
+        let got = store.get("hello").unwrap().unwrap();
+        assert_eq!("world", String::from_utf8_lossy(&got));
+        assert_eq!(None, store.get("missing").unwrap());
+    }
+
+    #[test]
+    fn disk_store_rebuilds_keydir_on_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+
+        let key_value_pairs = vec![
+            ("hello", "world"),
+            ("first_name", "john"),
+            ("last_name", "smith"),
+        ];
+        {
+            let mut store = DiskStore::open(&path).unwrap();
+            for (key, value) in &key_value_pairs {
+                store.put(*key, *value).unwrap();
+            }
+            store.put("first_name", "jane").unwrap();
+            store.delete("hello").unwrap();
+        }
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(None, store.get("hello").unwrap());
+        let got = store.get("first_name").unwrap().unwrap();
+        assert_eq!("jane", String::from_utf8_lossy(&got));
+        let got = store.get("last_name").unwrap().unwrap();
+        assert_eq!("smith", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn disk_store_opens_from_a_hint_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        {
+            let mut store = DiskStore::open(&path).unwrap();
+            store.put("hello", "world").unwrap();
+            store.put("first_name", "john").unwrap();
+            store.write_hint_file().unwrap();
+        }
+        assert!(path.with_extension("hint").exists());
+
+        let mut store = DiskStore::open(&path).unwrap();
+        let got = store.get("hello").unwrap().unwrap();
+        assert_eq!("world", String::from_utf8_lossy(&got));
+
+        store.put("hello", "again").unwrap();
+        assert!(!path.with_extension("hint").exists());
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        let got = store.get("hello").unwrap().unwrap();
+        assert_eq!("again", String::from_utf8_lossy(&got));
+        let got = store.get("first_name").unwrap().unwrap();
+        assert_eq!("john", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn disk_store_hint_keeps_the_seq_of_a_trailing_tombstone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        // Every write lands in the same second, so only `seq` orders them.
+        let options = Options::new().clock(Arc::new(MockClock::new(1_700_000_000)));
+        let mut store = DiskStore::open_with(&path, options.clone()).unwrap();
+        store.put("a", "1").unwrap();
+        store.delete("a").unwrap();
+        store.close().unwrap();
+
+        let mut store = DiskStore::open_with(&path, options.clone()).unwrap();
+        assert!(store.has_hint);
+        store.put("a", "2").unwrap();
+        // A crash: no hint gets written, so the next open scans the file.
+        std::mem::forget(store);
+        assert!(!path.with_extension("hint").exists());
+
+        let store = DiskStore::open_with(&path, options).unwrap();
+        assert_eq!(Some(b"2".to_vec()), store.get("a").unwrap());
+    }
+
+    #[test]
+    fn disk_store_scans_past_a_hint_cut_short() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let hint_path = path.with_extension("hint");
+        {
+            let mut store = DiskStore::open(&path).unwrap();
+            store.put("hello", "world").unwrap();
+            store.put("first_name", "john").unwrap();
+            store.close().unwrap();
+        }
+        // The hint is renamed into place, leaving no temporary file behind.
+        assert_eq!(2, fs::read_dir(dir.path()).unwrap().count());
+        let hint = fs::read(&hint_path).unwrap();
+        fs::write(&hint_path, &hint[..hint.len() - 4]).unwrap();
+
+        let store = DiskStore::open(&path).unwrap();
+        assert!(!store.has_hint);
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").unwrap());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_respects_sync_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let cases = vec![
+            (SyncMode::Never, 0),
+            (SyncMode::Always, 10),
+            (SyncMode::EveryN(3), 3),
+        ];
+
+        for (i, (sync_mode, want)) in cases.into_iter().enumerate() {
+            let options = Options::new().sync_mode(sync_mode);
+            let path = dir.path().join(format!("{i}.data"));
+            let mut store = DiskStore::open_with(path, options).unwrap();
+            for i in 0..10 {
+                store.put("hello", i).unwrap();
+            }
+            assert_eq!(want, store.file.sync_count, "{sync_mode:?}");
+        }
+    }
+
+    #[test]
+    fn disk_store_can_sync_explicitly() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = DiskStore::open(dir.path().join("caska.data")).unwrap();
+        store.put("hello", "world").unwrap();
+
+        store.sync().unwrap();
+
+        assert_eq!(1, store.file.sync_count);
+        assert_eq!(0, store.file.unsynced_writes);
+    }
+
+    #[test]
+    fn disk_store_resolves_paths_against_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().data_dir(dir.path());
+
+        let mut store = DiskStore::open_with("caska.data", options).unwrap();
+        store.put("hello", "world").unwrap();
+
+        assert!(dir.path().join("caska.data").exists());
+    }
+
+    #[test]
+    fn disk_store_syncs_a_batch_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().sync_mode(SyncMode::Always);
+        let mut store = DiskStore::open_with(dir.path().join("caska.data"), options).unwrap();
+
+        store
+            .put_many(vec![
+                ("hello", "world"),
+                ("first_name", "john"),
+                ("last_name", "smith"),
+            ])
+            .unwrap();
+
+        assert_eq!(1, store.file.sync_count);
+        let got = store.get("last_name").unwrap().unwrap();
+        assert_eq!("smith", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn disk_store_discards_a_truncated_entry_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        {
+            let mut store = DiskStore::open(&path).unwrap();
+            store.put("hello", "world").unwrap();
+            store.put("first_name", "john").unwrap();
+        }
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 2)
+            .unwrap();
+
+        let mut store = DiskStore::open(&path).unwrap();
+        assert_eq!(1, store.recovery().entries_loaded);
+        assert!(store.recovery().bytes_truncated > 0);
+        assert_eq!(None, store.get("first_name").unwrap());
+
+        store.put("last_name", "smith").unwrap();
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(
+            RecoveryInfo::default().bytes_truncated,
+            store.recovery().bytes_truncated
+        );
+        let got = store.get("last_name").unwrap().unwrap();
+        assert_eq!("smith", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn disk_store_can_be_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put("hello", "world").unwrap();
+        store.write_hint_file().unwrap();
+
+        store.clear().unwrap();
+
+        assert_eq!(None, store.get("hello").unwrap());
+        assert_eq!(FileHeader::SIZE as u64, fs::metadata(&path).unwrap().len());
+        assert!(!path.with_extension("hint").exists());
+
+        store.put("first_name", "john").unwrap();
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(None, store.get("hello").unwrap());
+        let got = store.get("first_name").unwrap().unwrap();
+        assert_eq!("john", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn disk_store_rejects_files_that_are_not_caska_data_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        fs::write(&path, b"hello world").unwrap();
+
+        let err = DiskStore::open(&path).unwrap_err();
+        assert!(matches!(err, CaskaError::InvalidMagic));
+    }
+
+    #[test]
+    fn disk_store_compresses_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let options = Options::new().compression(Compression::Zstd);
+        let value = "a".repeat(1024);
+
+        let mut store = DiskStore::open_with(&path, options.clone()).unwrap();
+        store.put("hello", value.as_str()).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < value.len() as u64);
+        drop(store);
+
+        let store = DiskStore::open_with(&path, options).unwrap();
+        assert_eq!(Some(value.into_bytes()), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn disk_store_round_trips_binary_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put(b"\x00\x01", "null").unwrap();
+        store.put(b"\xc3\x28", "invalid utf-8").unwrap();
+        store.write_hint_file().unwrap();
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(Some(b"null".to_vec()), store.get(b"\x00\x01").unwrap());
+        assert_eq!(
+            Some(b"invalid utf-8".to_vec()),
+            store.get(b"\xc3\x28").unwrap()
+        );
+        assert_eq!(None, store.get(b"\x00").unwrap());
+    }
+
+    #[test]
+    fn disk_store_reads_through_mmap_match_buffered_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        for i in 0..100u32 {
+            store.put(i, i.to_string()).unwrap();
+        }
+        drop(store);
+
+        let buffered = DiskStore::open(&path).unwrap();
+        let mut mapped = DiskStore::open_with(&path, Options::new().use_mmap(true)).unwrap();
+        for i in 0..100u32 {
+            assert_eq!(buffered.get(i).unwrap(), mapped.get(i).unwrap());
+        }
+
+        mapped.put("hello", "world").unwrap();
+        assert_eq!(Some(b"world".to_vec()), mapped.get("hello").unwrap());
+        mapped.clear().unwrap();
+        mapped.put("first_name", "john").unwrap();
+        assert_eq!(Some(b"john".to_vec()), mapped.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_get_cow_borrows_from_the_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        for use_mmap in [false, true] {
+            let mut store = DiskStore::open_with(&path, Options::new().use_mmap(use_mmap)).unwrap();
+            store.put("hello", "world").unwrap();
+
+            let got = store.get_cow("hello").unwrap().unwrap();
+            assert_eq!(store.get("hello").unwrap().unwrap(), &*got);
+            assert_eq!(use_mmap, matches!(got, Cow::Borrowed(_)));
+            assert_eq!(None, store.get_cow("missing").unwrap());
+        }
+    }
+
+    #[test]
+    fn disk_store_get_checks_checksums_only_when_asked_to() {
+        for (verify_on_read, use_mmap) in [(true, false), (true, true), (false, false)] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("caska.data");
+            let options = Options::new()
+                .verify_on_read(verify_on_read)
+                .use_mmap(use_mmap);
+            let mut store = DiskStore::open_with(&path, options).unwrap();
+            store.put("hello", "world").unwrap();
+
+            let len = fs::metadata(&path).unwrap().len();
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(len - 1)).unwrap();
+            file.write_all(b"D").unwrap();
+
+            let got = store.get("hello");
+            if verify_on_read {
+                assert!(matches!(got, Err(CaskaError::ChecksumMismatch)));
+            } else {
+                assert_eq!(Some(b"worlD".to_vec()), got.unwrap());
+            }
+        }
+    }
+
+    /// A data file with an overwritten and a deleted key, and no hint.
+    fn write_unhinted(path: &Path) {
+        let mut store = DiskStore::open(path).unwrap();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "there").unwrap();
+        store.delete("first_name").unwrap();
+        drop(store);
+        fs::remove_file(path.with_extension("hint")).unwrap();
+    }
+
+    #[test]
+    fn lazy_recovery_defers_indexing_to_the_first_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        write_unhinted(&path);
+        let options = Options::new().recovery(RecoveryMode::Lazy);
+
+        let mut store = DiskStore::open_with(&path, options.clone()).unwrap();
+        assert!(store.keydir.is_empty());
+        let deferred = store.deferred.as_ref().unwrap();
+        assert!(deferred.lock().unwrap().is_none());
+
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+        assert!(deferred.lock().unwrap().is_some());
+        assert_eq!(None, store.get("first_name").unwrap());
+
+        store.put("last_name", "smith").unwrap();
+        assert!(store.deferred.is_none());
+        assert_eq!(2, store.keydir.len());
+        drop(store);
+
+        let store = DiskStore::open_with(&path, options).unwrap();
+        assert!(store.deferred.is_none(), "the hint written on drop is used");
+        assert_eq!(Some(b"smith".to_vec()), store.get("last_name").unwrap());
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn lazy_recovery_repairs_a_torn_tail_but_not_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let options = Options::new().recovery(RecoveryMode::Lazy);
+        {
+            let mut store = DiskStore::open(&path).unwrap();
+            store.put("blob", vec![0xff; 64]).unwrap();
+            store.put("hello", "world").unwrap();
+        }
+        fs::remove_file(path.with_extension("hint")).unwrap();
+        let valid = fs::read(&path).unwrap();
+
+        let mut data = valid.clone();
+        append_torn_entry(&mut data);
+        fs::write(&path, &data).unwrap();
+        let mut store = DiskStore::open_with(&path, options.clone()).unwrap();
+        store.put("first_name", "john").unwrap();
+        assert_eq!(data.len() - valid.len(), store.recovery().bytes_truncated);
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").unwrap());
+        drop(store);
+
+        // Shrinking the blob's `value_sz` sends the index into its 0xff
+        // bytes, which read as a key far longer than the file.
+        let mut data = valid.clone();
+        let value_sz = FileHeader::SIZE + KeyValueEntry::HEADER_SIZE - 4;
+        data[value_sz..value_sz + 4].copy_from_slice(&2u32.to_be_bytes());
+        fs::write(&path, &data).unwrap();
+        let mut store = DiskStore::open_with(&path, options).unwrap();
+        let err = store.put("first_name", "john").unwrap_err();
+        assert!(matches!(err, CaskaError::ChecksumMismatch));
+        assert!(matches!(
+            store.get("hello").unwrap_err(),
+            CaskaError::ChecksumMismatch
+        ));
+        drop(store);
+        assert_eq!(data, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn hint_only_recovery_refuses_to_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let options = Options::new().recovery(RecoveryMode::HintOnly);
+        DiskStore::open_with(&path, options.clone()).unwrap();
+
+        write_unhinted(&path);
+        let err = DiskStore::open_with(&path, options.clone()).unwrap_err();
+        assert!(matches!(err, CaskaError::HintUnavailable));
+
+        DiskStore::open(&path).unwrap().close().unwrap();
+        let store = DiskStore::open_with(&path, options).unwrap();
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn disk_store_reads_see_buffered_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let options = Options::new().write_buffer_size(1024);
+        let mut store = DiskStore::open_with(&path, options).unwrap();
+        let empty_len = fs::metadata(&path).unwrap().len();
+
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.delete("hello").unwrap();
+        assert_eq!(empty_len, fs::metadata(&path).unwrap().len());
+        assert_eq!(None, store.get("hello").unwrap());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+
+        store.flush().unwrap();
+        assert_eq!(store.file.size(), fs::metadata(&path).unwrap().len());
+        assert!(store.file.size() > empty_len);
+        assert_eq!(None, store.get("hello").unwrap());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_flushes_once_the_write_buffer_fills() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let options = Options::new().write_buffer_size(256);
+        let mut store = DiskStore::open_with(&path, options).unwrap();
+
+        for i in 0..10u32 {
+            store.put(i, "a".repeat(40)).unwrap();
+        }
+        let on_disk = fs::metadata(&path).unwrap().len();
+        assert!(on_disk > FileHeader::SIZE as u64);
+        assert!(on_disk < store.file.size());
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        for i in 0..10u32 {
+            assert_eq!(Some(b"a".repeat(40)), store.get(i).unwrap());
+        }
+    }
+
+    #[test]
+    fn disk_store_merge_keeps_recent_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let options = Options::new()
+            .clock(clock.clone())
+            .tombstone_ttl(Duration::from_secs(60));
+        let mut store = DiskStore::open_with(&path, options).unwrap();
+        store.put("old", "value").unwrap();
+        store.delete("old").unwrap();
+        clock.advance(120);
+        store.put("fresh", "value").unwrap();
+        store.delete("fresh").unwrap();
+        store.put("live", "value").unwrap();
+
+        let report = store.merge().unwrap();
+
+        let tombstones: Vec<_> = store
+            .file
+            .entry_headers()
+            .map(Result::unwrap)
+            .filter(KeyValueEntry::is_tombstone)
+            .map(|entry| (entry.key, entry.tstamp))
+            .collect();
+        assert_eq!(vec![(b"fresh".to_vec(), 1_700_000_120)], tombstones);
+        assert_eq!(2, report.entries_after);
+        assert_eq!(None, store.get("fresh").unwrap());
+        assert_eq!(Some(b"value".to_vec()), store.get("live").unwrap());
+    }
+
+    #[test]
+    fn disk_store_merge_keeps_only_live_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        for i in 0..100u32 {
+            store.put("hello", i).unwrap();
+        }
+        store.put("first_name", "john").unwrap();
+        store.delete("first_name").unwrap();
+        let size_before = fs::metadata(&path).unwrap().len();
+
+        let report = store.merge().unwrap();
+
+        let size_after = fs::metadata(&path).unwrap().len();
+        assert!(size_after * 10 < size_before);
+        assert_eq!(102, report.entries_before);
+        assert_eq!(1, report.entries_after);
+        assert_eq!(size_before as usize, report.bytes_before);
+        assert_eq!(size_after as usize, report.bytes_after);
+        assert_eq!(
+            report.bytes_before - report.bytes_after,
+            report.reclaimed_bytes
+        );
+        assert!(!path.with_extension(MERGE_EXTENSION).exists());
+        assert_eq!(Some(99u32.to_bytes()), store.get("hello").unwrap());
+        store.put("last_name", "smith").unwrap();
+        drop(store);
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(Some(99u32.to_bytes()), store.get("hello").unwrap());
+        assert_eq!(None, store.get("first_name").unwrap());
+        assert_eq!(Some(b"smith".to_vec()), store.get("last_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_interrupted_merge_leaves_the_original_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put("hello", "world").unwrap();
+        store.put("hello", "there").unwrap();
+        store.put("first_name", "john").unwrap();
+        let original = fs::read(&path).unwrap();
+
+        // Stop after the compacted copy is written, as a crash would.
+        store.write_merged().unwrap();
+        drop(store);
+        assert!(path.with_extension(MERGE_EXTENSION).exists());
+        assert_eq!(original, fs::read(&path).unwrap());
+
+        let store = DiskStore::open(&path).unwrap();
+        assert!(!path.with_extension(MERGE_EXTENSION).exists());
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_writes_a_hint_on_close_and_prefers_it_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.close().unwrap();
+        assert!(path.with_extension("hint").exists());
+
+        // Loading from the hint doesn't replay any entries.
+        let store = DiskStore::open(&path).unwrap();
+        assert!(store.has_hint);
+        assert_eq!(0, store.recovery().entries_loaded);
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").unwrap());
+        drop(store);
+
+        fs::remove_file(path.with_extension("hint")).unwrap();
+        let store = DiskStore::open(&path).unwrap();
+        assert!(!store.has_hint);
+        assert_eq!(2, store.recovery().entries_loaded);
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn disk_store_ignores_a_hint_older_than_the_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("caska.data");
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put("hello", "world").unwrap();
+        drop(store);
+        let stale_hint = fs::read(path.with_extension("hint")).unwrap();
+
+        let mut store = DiskStore::open(&path).unwrap();
+        store.put("hello", "there").unwrap();
+        drop(store);
+        fs::write(path.with_extension("hint"), stale_hint).unwrap();
+
+        let store = DiskStore::open(&path).unwrap();
+        assert_eq!(2, store.recovery().entries_loaded);
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+    }
+}
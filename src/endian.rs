@@ -0,0 +1,84 @@
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+/// Byte order used for the integer fields of entries and hint records.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl Endianness {
+    /// Tag recorded in the file header.
+    pub(crate) fn tag(self) -> u8 {
+        match self {
+            Self::Big => 0,
+            Self::Little => 1,
+        }
+    }
+
+    pub(crate) fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Big),
+            1 => Some(Self::Little),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn read_u32<R: Read>(self, r: &mut R) -> io::Result<u32> {
+        match self {
+            Self::Big => r.read_u32::<BigEndian>(),
+            Self::Little => r.read_u32::<LittleEndian>(),
+        }
+    }
+
+    pub(crate) fn read_u64<R: Read>(self, r: &mut R) -> io::Result<u64> {
+        match self {
+            Self::Big => r.read_u64::<BigEndian>(),
+            Self::Little => r.read_u64::<LittleEndian>(),
+        }
+    }
+
+    pub(crate) fn write_u32<W: Write>(self, w: &mut W, n: u32) -> io::Result<()> {
+        match self {
+            Self::Big => w.write_u32::<BigEndian>(n),
+            Self::Little => w.write_u32::<LittleEndian>(n),
+        }
+    }
+
+    pub(crate) fn write_u64<W: Write>(self, w: &mut W, n: u64) -> io::Result<()> {
+        match self {
+            Self::Big => w.write_u64::<BigEndian>(n),
+            Self::Little => w.write_u64::<LittleEndian>(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_are_written_in_the_chosen_order() {
+        let mut big = vec![];
+        Endianness::Big.write_u32(&mut big, 42).unwrap();
+        let mut little = vec![];
+        Endianness::Little.write_u32(&mut little, 42).unwrap();
+
+        assert_eq!(vec![0, 0, 0, 42], big);
+        assert_eq!(vec![42, 0, 0, 0], little);
+        assert_eq!(
+            42,
+            Endianness::Little.read_u32(&mut little.as_slice()).unwrap()
+        );
+    }
+
+    #[test]
+    fn tags_round_trip() {
+        for order in [Endianness::Big, Endianness::Little] {
+            assert_eq!(Some(order), Endianness::from_tag(order.tag()));
+        }
+        assert_eq!(None, Endianness::from_tag(2));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    error::{CaskaError, Result},
+    MemoryStore, ToBytes,
+};
+use std::borrow::Cow;
+
+/// A view of one key for read-modify-write, from [`MemoryStore::entry`].
+#[derive(Debug)]
+pub(crate) enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+/// A key with a live value.
+#[derive(Debug)]
+pub(crate) struct OccupiedEntry<'a> {
+    store: &'a mut MemoryStore,
+    key: Vec<u8>,
+}
+
+/// A key that is missing, deleted or expired.
+#[derive(Debug)]
+pub(crate) struct VacantEntry<'a> {
+    store: &'a mut MemoryStore,
+    key: Vec<u8>,
+}
+
+impl MemoryStore {
+    pub(crate) fn entry<K: ToBytes>(&mut self, key: K) -> Entry<'_> {
+        let key = key.to_bytes();
+        if self.live_entry(&key).is_some() {
+            Entry::Occupied(OccupiedEntry { store: self, key })
+        } else {
+            Entry::Vacant(VacantEntry { store: self, key })
+        }
+    }
+}
+
+impl Entry<'_> {
+    pub(crate) fn key(&self) -> &[u8] {
+        match self {
+            Self::Occupied(entry) => &entry.key,
+            Self::Vacant(entry) => &entry.key,
+        }
+    }
+
+    /// Rewrites an occupied key with whatever `f` makes of its value. Vacant
+    /// keys are left alone.
+    pub(crate) fn and_modify<F: FnOnce(&mut Vec<u8>)>(self, f: F) -> Result<Self> {
+        match self {
+            Self::Occupied(mut entry) => {
+                let mut value = entry.get()?;
+                f(&mut value);
+                entry.insert(value)?;
+                Ok(Self::Occupied(entry))
+            }
+            vacant => Ok(vacant),
+        }
+    }
+
+    /// Returns the current value, first writing `default` if the key is
+    /// vacant.
+    pub(crate) fn or_insert<V: ToBytes>(self, default: V) -> Result<Vec<u8>> {
+        match self {
+            Self::Occupied(entry) => entry.get(),
+            Self::Vacant(entry) => entry.insert(default),
+        }
+    }
+}
+
+impl OccupiedEntry<'_> {
+    pub(crate) fn get(&self) -> Result<Vec<u8>> {
+        // Only eviction under `max_entries` can take the key away.
+        let entry = self
+            .store
+            .live_entry(&self.key)
+            .ok_or_else(|| CaskaError::InvalidEntry("key was evicted".into()))?;
+        self.store.try_value(entry).map(Cow::into_owned)
+    }
+
+    pub(crate) fn insert<V: ToBytes>(&mut self, value: V) -> Result<()> {
+        self.store.put(self.key.clone(), value)
+    }
+}
+
+impl VacantEntry<'_> {
+    pub(crate) fn insert<V: ToBytes>(self, value: V) -> Result<Vec<u8>> {
+        let value = value.to_bytes();
+        self.store.put(self.key, value.clone())?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append_bang(value: &mut Vec<u8>) {
+        value.push(b'!');
+    }
+
+    #[test]
+    fn entry_modifies_present_keys_and_fills_absent_ones() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+
+        let got = store
+            .entry("hello")
+            .and_modify(append_bang)
+            .unwrap()
+            .or_insert("default")
+            .unwrap();
+        assert_eq!(b"world!".to_vec(), got);
+        assert_eq!(Some(b"world!".to_vec()), store.get("hello"));
+
+        let got = store
+            .entry("first_name")
+            .and_modify(append_bang)
+            .unwrap()
+            .or_insert("john")
+            .unwrap();
+        assert_eq!(b"john".to_vec(), got);
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name"));
+    }
+
+    #[test]
+    fn deleted_keys_are_vacant() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.delete("hello").unwrap();
+
+        let entry = store.entry("hello");
+        assert!(matches!(entry, Entry::Vacant(_)));
+        assert_eq!(b"hello", entry.key());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::endian::Endianness;
+use std::{error::Error, fmt, io};
+
+pub(crate) type Result<T, E = CaskaError> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub(crate) enum CaskaError {
+    Io(io::Error),
+    /// An entry's stored checksum doesn't match its contents.
+    ChecksumMismatch,
+    /// An entry ended before all of its bytes could be read.
+    Truncated,
+    /// Keys must be at least one byte long.
+    EmptyKey,
+    KeyTooLarge,
+    ValueTooLarge,
+    /// The keydir points at bytes that aren't a valid entry.
+    InvalidEntry(String),
+    /// A stored value couldn't be decoded as the requested type.
+    InvalidValue(String),
+    Unsupported(&'static str),
+    /// A value couldn't be encoded with the configured serde codec.
+    Serialize(String),
+    /// A stored value couldn't be decoded with the configured serde codec.
+    Deserialize(String),
+    /// A stored value couldn't be decompressed.
+    Compression(String),
+    /// The data doesn't start with a caska file header.
+    InvalidMagic,
+    UnsupportedVersion {
+        found: u16,
+        expected: u16,
+    },
+    /// `RecoveryMode::HintOnly` found no hint matching the data file.
+    HintUnavailable,
+    /// Another handle already holds the data directory's lock file.
+    AlreadyLocked,
+    /// The store was opened read-only.
+    ReadOnly,
+    /// The file was written in a different byte order than requested.
+    ByteOrderMismatch {
+        found: Endianness,
+        expected: Endianness,
+    },
+}
+
+impl fmt::Display for CaskaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "io error: {err}"),
+            Self::ChecksumMismatch => write!(f, "checksum mismatch"),
+            Self::Truncated => write!(f, "entry is truncated"),
+            Self::EmptyKey => write!(f, "key is empty"),
+            Self::KeyTooLarge => write!(f, "key is too large"),
+            Self::ValueTooLarge => write!(f, "value is too large"),
+            Self::InvalidEntry(msg) => write!(f, "invalid entry: {msg}"),
+            Self::InvalidValue(msg) => write!(f, "invalid value: {msg}"),
+            Self::Unsupported(msg) => write!(f, "unsupported: {msg}"),
+            Self::Serialize(msg) => write!(f, "serialize error: {msg}"),
+            Self::Deserialize(msg) => write!(f, "deserialize error: {msg}"),
+            Self::Compression(msg) => write!(f, "compression error: {msg}"),
+            Self::InvalidMagic => write!(f, "not a caska data file"),
+            Self::HintUnavailable => write!(f, "no current hint file to recover from"),
+            Self::AlreadyLocked => write!(f, "data directory is locked by another process"),
+            Self::ReadOnly => write!(f, "store is read-only"),
+            Self::ByteOrderMismatch { found, expected } => {
+                write!(f, "file is {found:?}-endian, expected {expected:?}-endian")
+            }
+            Self::UnsupportedVersion { found, expected } => {
+                write!(f, "unsupported format version {found}, expected {expected}")
+            }
+        }
+    }
+}
+
+impl Error for CaskaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CaskaError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,41 @@
+use std::{fmt, sync::Arc};
+
+/// A mutation that has just been applied to a store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StoreEvent {
+    /// `value_sz` is the size of the value as stored, after any compression.
+    Put {
+        key: Vec<u8>,
+        value_sz: u32,
+    },
+    Delete {
+        key: Vec<u8>,
+    },
+    Merge {
+        reclaimed_bytes: usize,
+    },
+    /// A partially written entry was cut from the end of a file on open.
+    Repaired {
+        truncated_bytes: usize,
+    },
+}
+
+/// Callback registered with [`crate::options::Options::on_event`].
+#[derive(Clone)]
+pub(crate) struct EventHandler(Arc<dyn Fn(&StoreEvent) + Send + Sync>);
+
+impl EventHandler {
+    pub(crate) fn new(f: Box<dyn Fn(&StoreEvent) + Send + Sync>) -> Self {
+        Self(Arc::from(f))
+    }
+
+    pub(crate) fn call(&self, event: &StoreEvent) {
+        (self.0)(event);
+    }
+}
+
+impl fmt::Debug for EventHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventHandler(..)")
+    }
+}
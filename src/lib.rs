@@ -1,12 +1,47 @@
 #![allow(dead_code, clippy::cast_possible_truncation)]
-use anyhow::Result;
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt, WriteBytesExt};
 use std::{
-    collections::HashMap,
-    io::{Cursor, Read, Write},
-    time::{SystemTime, UNIX_EPOCH},
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    iter, mem,
+    ops::{Bound, RangeBounds},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+#[cfg(feature = "async")]
+mod async_store;
+mod bloom;
+mod buffer;
+mod checksum;
+mod clock;
+#[cfg(feature = "serde")]
+mod codec;
+mod compression;
+mod disk;
+mod endian;
+mod entry;
+mod error;
+mod event;
+mod lru;
+mod options;
+mod shared;
+mod store;
+#[cfg(test)]
+mod testing;
+
+use bloom::BloomFilter;
+use checksum::ChecksumKind;
+use compression::Compression;
+use endian::Endianness;
+use error::{CaskaError, Result};
+use event::StoreEvent;
+use lru::Lru;
+use options::Options;
+
 trait ToBytes {
     fn to_bytes(self) -> Vec<u8>;
 }
@@ -19,21 +54,118 @@ impl ToBytes for u32 {
     }
 }
 
+impl ToBytes for u64 {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![0; 8];
+        BigEndian::write_u64(&mut bytes, self);
+        bytes
+    }
+}
+
+impl ToBytes for i32 {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![0; 4];
+        BigEndian::write_i32(&mut bytes, self);
+        bytes
+    }
+}
+
+impl ToBytes for i64 {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![0; 8];
+        BigEndian::write_i64(&mut bytes, self);
+        bytes
+    }
+}
+
+impl ToBytes for bool {
+    fn to_bytes(self) -> Vec<u8> {
+        vec![u8::from(self)]
+    }
+}
+
 impl ToBytes for &str {
     fn to_bytes(self) -> Vec<u8> {
         self.as_bytes().to_vec()
     }
 }
 
+impl ToBytes for String {
+    fn to_bytes(self) -> Vec<u8> {
+        self.into_bytes()
+    }
+}
+
+impl ToBytes for &[u8] {
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
+impl<const N: usize> ToBytes for &[u8; N] {
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_vec()
+    }
+}
+
 impl ToBytes for Vec<u8> {
     fn to_bytes(self) -> Vec<u8> {
         self
     }
 }
 
+trait FromBytes: Sized {
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+impl FromBytes for u32 {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != size_of::<u32>() {
+            return Err(CaskaError::InvalidValue(format!(
+                "expected 4 bytes for u32, got {}",
+                bytes.len()
+            )));
+        }
+        Ok(BigEndian::read_u32(bytes))
+    }
+}
+
+impl FromBytes for u64 {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != size_of::<u64>() {
+            return Err(CaskaError::InvalidValue(format!(
+                "expected 8 bytes for u64, got {}",
+                bytes.len()
+            )));
+        }
+        Ok(BigEndian::read_u64(bytes))
+    }
+}
+
+impl FromBytes for String {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| CaskaError::InvalidValue(err.to_string()))
+    }
+}
+
+impl FromBytes for Vec<u8> {
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Sentinel stored in `value_sz` to mark a deleted key.
+const TOMBSTONE: u32 = u32::MAX;
+
 #[derive(Debug, PartialEq)]
 struct KeyValueEntry {
-    tstamp: u32,
+    tstamp: u64,
+    /// Per-store write counter that orders entries sharing a `tstamp`.
+    seq: u64,
+    /// Timestamp after which the entry is treated as deleted, or 0 if it never expires.
+    expiry: u64,
+    /// Bit flags describing how `value` is stored, e.g. compressed.
+    flags: u8,
     ksz: u32,
     value_sz: u32,
     key: Vec<u8>,
@@ -41,7 +173,7 @@ struct KeyValueEntry {
 }
 
 impl KeyValueEntry {
-    fn new<K, V>(tstamp: u32, key: K, value: V) -> Self
+    fn new<K, V>(tstamp: u64, key: K, value: V) -> Self
     where
         K: ToBytes,
         V: ToBytes,
@@ -50,6 +182,9 @@ impl KeyValueEntry {
         let value = value.to_bytes();
         Self {
             tstamp,
+            seq: 0,
+            expiry: 0,
+            flags: 0,
             ksz: key.len() as u32,
             value_sz: value.len() as u32,
             key,
@@ -57,173 +192,4225 @@ impl KeyValueEntry {
         }
     }
 
+    fn tombstone<K: ToBytes>(tstamp: u64, key: K) -> Self {
+        let key = key.to_bytes();
+        Self {
+            tstamp,
+            seq: 0,
+            expiry: 0,
+            flags: 0,
+            ksz: key.len() as u32,
+            value_sz: TOMBSTONE,
+            key,
+            value: vec![],
+        }
+    }
+
+    fn with_expiry(mut self, expiry: u64) -> Self {
+        self.expiry = expiry;
+        self
+    }
+
+    fn with_seq(mut self, seq: u64) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    fn with_stored_value(mut self, flags: u8, value: Vec<u8>) -> Self {
+        self.flags = flags;
+        self.value_sz = value.len() as u32;
+        self.value = value;
+        self
+    }
+
+    /// Rebuilds the entry `keydir_entry` describes from its stored value,
+    /// keeping its version, expiry and flags so it can be copied elsewhere.
+    fn restore(key: Vec<u8>, keydir_entry: &KeyDirEntry, stored: Vec<u8>) -> Self {
+        Self::new(keydir_entry.tstamp, key, vec![])
+            .with_stored_value(keydir_entry.flags, stored)
+            .with_expiry(keydir_entry.expiry)
+            .with_seq(keydir_entry.seq)
+    }
+
+    fn compress(self, compression: Compression) -> Result<Self> {
+        if self.is_tombstone() {
+            return Ok(self);
+        }
+        let (flags, value) = compression.compress(self.value.clone())?;
+        Ok(self.with_stored_value(flags, value))
+    }
+
+    fn is_tombstone(&self) -> bool {
+        self.value_sz == TOMBSTONE
+    }
+
+    /// Encoded size of the fixed-width fields: crc, tstamp, seq, expiry,
+    /// flags, ksz and value_sz.
+    const HEADER_SIZE: usize = 3 * size_of::<u64>() + size_of::<u8>() + 3 * size_of::<u32>();
+
+    /// Reads an entry's header and key, then seeks past its value, which is
+    /// left empty. The checksum can't be checked without the value.
+    fn read_indexed<R: Read + Seek>(reader: &mut R, order: Endianness) -> Result<Self> {
+        let mut header = [0; Self::HEADER_SIZE];
+        match reader.read_exact(&mut header) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(CaskaError::Truncated)
+            }
+            result => result?,
+        }
+        let mut fields = &header[size_of::<u32>()..];
+        let tstamp = order.read_u64(&mut fields)?;
+        let seq = order.read_u64(&mut fields)?;
+        let expiry = order.read_u64(&mut fields)?;
+        let flags = fields.read_u8()?;
+        let ksz = order.read_u32(&mut fields)?;
+        let value_sz = order.read_u32(&mut fields)?;
+
+        let key = read_bytes(reader, ksz)?;
+        let entry = Self {
+            tstamp,
+            seq,
+            expiry,
+            flags,
+            ksz,
+            value_sz,
+            key,
+            value: vec![],
+        };
+        if !entry.is_tombstone() {
+            reader.seek(SeekFrom::Current(i64::from(value_sz)))?;
+        }
+        Ok(entry)
+    }
+
+    /// Size on disk going by `value_sz`, for entries read without their
+    /// value.
+    fn encoded_size(&self) -> usize {
+        let value_sz = if self.is_tombstone() {
+            0
+        } else {
+            self.value_sz
+        };
+        self.value_offset() + value_sz as usize
+    }
+
+    /// Checks an encoded entry's checksum without decoding its fields.
+    fn verify_checksum(encoded: &[u8], order: Endianness, checksum: ChecksumKind) -> Result<()> {
+        let (mut crc, body) = encoded.split_at(size_of::<u32>());
+        if order.read_u32(&mut crc)? != checksum.checksum(body) {
+            return Err(CaskaError::ChecksumMismatch);
+        }
+        Ok(())
+    }
+
     fn value_offset(&self) -> usize {
-        3 * size_of::<u32>() + self.key.len()
+        Self::HEADER_SIZE + self.key.len()
     }
-}
 
-impl TryFrom<KeyValueEntry> for Vec<u8> {
-    type Error = anyhow::Error;
+    fn size(&self) -> usize {
+        self.value_offset() + self.value.len()
+    }
 
-    fn try_from(entry: KeyValueEntry) -> Result<Self> {
-        let mut buf: Vec<u8> = vec![];
-        buf.write_u32::<BigEndian>(entry.tstamp)?;
-        buf.write_u32::<BigEndian>(entry.ksz)?;
-        buf.write_u32::<BigEndian>(entry.value_sz)?;
-        buf.write_all(&entry.key)?;
-        buf.write_all(&entry.value)?;
-        Ok(buf)
+    /// Rejects keys and values the options don't allow. Empty values are
+    /// fine; an empty key isn't, so every stored key has at least one byte.
+    fn check_size(&self, options: &Options) -> Result<()> {
+        if self.key.is_empty() {
+            return Err(CaskaError::EmptyKey);
+        }
+        if self.key.len() > options.max_key_size {
+            return Err(CaskaError::KeyTooLarge);
+        }
+        if self.value.len() > options.max_value_size {
+            return Err(CaskaError::ValueTooLarge);
+        }
+        Ok(())
     }
-}
 
-impl TryFrom<Vec<u8>> for KeyValueEntry {
-    type Error = anyhow::Error;
+    fn read_from<R: Read>(
+        reader: &mut R,
+        order: Endianness,
+        checksum: ChecksumKind,
+    ) -> Result<Self> {
+        match Self::read_fields(reader, order, checksum) {
+            Err(CaskaError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(CaskaError::Truncated)
+            }
+            result => result,
+        }
+    }
 
-    fn try_from(bytes: Vec<u8>) -> Result<Self> {
-        let mut cursor = Cursor::new(bytes);
+    fn read_fields<R: Read>(
+        reader: &mut R,
+        order: Endianness,
+        checksum: ChecksumKind,
+    ) -> Result<Self> {
+        let crc = order.read_u32(reader)?;
 
-        let tstamp = cursor.read_u32::<BigEndian>()?;
-        let ksz = cursor.read_u32::<BigEndian>()?;
-        let value_sz = cursor.read_u32::<BigEndian>()?;
+        let mut header = [0; Self::HEADER_SIZE - size_of::<u32>()];
+        reader.read_exact(&mut header)?;
+        let mut fields = &header[..];
+        let tstamp = order.read_u64(&mut fields)?;
+        let seq = order.read_u64(&mut fields)?;
+        let expiry = order.read_u64(&mut fields)?;
+        let flags = fields.read_u8()?;
+        let ksz = order.read_u32(&mut fields)?;
+        let value_sz = order.read_u32(&mut fields)?;
 
-        let mut key = vec![0; ksz as usize];
-        cursor.read_exact(&mut key)?;
+        let key = read_bytes(reader, ksz)?;
+        let value = if value_sz == TOMBSTONE {
+            vec![]
+        } else {
+            read_bytes(reader, value_sz)?
+        };
 
-        let mut value = vec![0; value_sz as usize];
-        cursor.read_exact(&mut value)?;
+        let mut hasher = checksum.hasher();
+        hasher.update(&header);
+        hasher.update(&key);
+        hasher.update(&value);
+        if hasher.finalize() != crc {
+            return Err(CaskaError::ChecksumMismatch);
+        }
 
         Ok(Self {
             tstamp,
+            seq,
+            expiry,
+            flags,
             ksz,
             value_sz,
             key,
             value,
         })
     }
+
+    fn encode(self, order: Endianness, checksum: ChecksumKind) -> Result<Vec<u8>> {
+        let mut body: Vec<u8> = vec![];
+        order.write_u64(&mut body, self.tstamp)?;
+        order.write_u64(&mut body, self.seq)?;
+        order.write_u64(&mut body, self.expiry)?;
+        body.write_u8(self.flags)?;
+        order.write_u32(&mut body, self.ksz)?;
+        order.write_u32(&mut body, self.value_sz)?;
+        body.write_all(&self.key)?;
+        body.write_all(&self.value)?;
+
+        let mut buf = Vec::with_capacity(size_of::<u32>() + body.len());
+        order.write_u32(&mut buf, checksum.checksum(&body))?;
+        buf.extend(body);
+        Ok(buf)
+    }
 }
 
-#[derive(Debug)]
+/// Encodes in the default byte order and checksum; stores use `encode` with
+/// their own.
+impl TryFrom<KeyValueEntry> for Vec<u8> {
+    type Error = CaskaError;
+
+    fn try_from(entry: KeyValueEntry) -> Result<Self> {
+        entry.encode(Endianness::default(), ChecksumKind::default())
+    }
+}
+
+/// Reads `len` bytes without allocating them up front, since `len` comes
+/// from a header whose checksum hasn't been checked yet. A corrupt length
+/// can only cost as much memory as the reader actually holds.
+fn read_bytes<R: Read>(reader: &mut R, len: u32) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader
+        .by_ref()
+        .take(u64::from(len))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() < len as usize {
+        return Err(CaskaError::Truncated);
+    }
+    Ok(bytes)
+}
+
+/// Decodes in the default byte order and checksum; stores use `read_from`
+/// with their own.
+impl TryFrom<Vec<u8>> for KeyValueEntry {
+    type Error = CaskaError;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Self::read_from(
+            &mut Cursor::new(bytes),
+            Endianness::default(),
+            ChecksumKind::default(),
+        )
+    }
+}
+
+/// A page of key/value pairs and the cursor to fetch the next one with.
+type Page = (Vec<(Vec<u8>, Vec<u8>)>, Option<Vec<u8>>);
+
+/// Keys are kept in byte order so range scans don't need to sort.
+type KeyDir = BTreeMap<Vec<u8>, KeyDirEntry>;
+
+#[derive(Debug, Clone, PartialEq)]
 struct KeyDirEntry {
     file_id: u32,
     value_sz: u32,
     value_pos: u32,
-    tstamp: u32,
+    tstamp: u64,
+    seq: u64,
+    expiry: u64,
+    flags: u8,
 }
 
-#[derive(Debug)]
-struct File {
-    id: u32,
-    data: Vec<u8>,
-}
+impl KeyDirEntry {
+    fn new(file_id: u32, entry_pos: usize, entry: &KeyValueEntry) -> Self {
+        Self {
+            file_id,
+            value_sz: entry.value_sz,
+            value_pos: (entry_pos + entry.value_offset()) as u32,
+            tstamp: entry.tstamp,
+            seq: entry.seq,
+            expiry: entry.expiry,
+            flags: entry.flags,
+        }
+    }
 
-impl File {
-    fn new(id: u32) -> Self {
-        Self { id, data: vec![] }
+    /// Which of two entries for a key was written last.
+    fn version(&self) -> (u64, u64) {
+        (self.tstamp, self.seq)
     }
 
-    fn size(&self) -> usize {
-        self.data.len()
+    fn is_expired(&self, now: u64) -> bool {
+        self.expiry != 0 && self.expiry <= now
     }
 
-    fn append(&mut self, data: &[u8]) {
-        self.data.extend_from_slice(data);
+    /// Size of the entry this points at, given its key.
+    fn encoded_size(&self, key: &[u8]) -> usize {
+        KeyValueEntry::HEADER_SIZE + key.len() + self.value_sz as usize
     }
 }
 
-#[derive(Debug)]
-struct MemoryStore {
-    file: File,
-    keydir: HashMap<Vec<u8>, KeyDirEntry>,
+/// What was salvaged when replaying a data file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct RecoveryInfo {
+    /// Trailing bytes of a partially written entry that were discarded.
+    bytes_truncated: usize,
+    entries_loaded: usize,
+    /// Highest sequence number seen, so new writes can continue after it.
+    last_seq: u64,
 }
 
-impl MemoryStore {
-    fn new() -> Self {
-        Self {
-            file: File::new(now()),
-            keydir: HashMap::new(),
+/// Rebuilds a keydir by replaying every entry in a data file, in write order.
+///
+/// A truncated final entry, as left behind by a crash mid-write, stops the
+/// scan instead of failing it.
+fn load_keydir(
+    file_id: u32,
+    data: &[u8],
+    order: Endianness,
+    now: u64,
+) -> Result<(KeyDir, RecoveryInfo)> {
+    let mut keydir = KeyDir::new();
+    let recovery = merge_keydir(file_id, data, order, now, &mut keydir, &mut HashMap::new())?;
+    Ok((keydir, recovery))
+}
+
+/// Folds one file's entries into `keydir`, keeping whichever entry has the
+/// newest `(tstamp, seq)` for each key; ties go to the entry read last.
+/// `removed` remembers when keys were deleted or expired so an older value
+/// read from another file can't bring them back.
+fn merge_keydir(
+    file_id: u32,
+    data: &[u8],
+    order: Endianness,
+    now: u64,
+    keydir: &mut KeyDir,
+    removed: &mut HashMap<Vec<u8>, (u64, u64)>,
+) -> Result<RecoveryInfo> {
+    let header = FileHeader::decode(data)?.check_byte_order(order)?;
+
+    let mut recovery = RecoveryInfo::default();
+    let mut cursor = Cursor::new(data);
+    cursor.set_position(FileHeader::SIZE as u64);
+
+    while (cursor.position() as usize) < data.len() {
+        let entry_pos = cursor.position() as usize;
+        let entry = match KeyValueEntry::read_from(&mut cursor, order, header.checksum) {
+            Ok(entry) => entry,
+            Err(CaskaError::Truncated) => {
+                recovery.bytes_truncated = data.len() - entry_pos;
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        recovery.entries_loaded += 1;
+        recovery.last_seq = recovery.last_seq.max(entry.seq);
+        apply_entry(file_id, entry_pos, entry, now, keydir, removed);
+    }
+
+    Ok(recovery)
+}
+
+/// Like `load_keydir`, but reads only each entry's header and key and seeks
+/// past its value, so checksums go unchecked until a value is read. The
+/// file header must already have been validated.
+fn index_keydir<R: Read + Seek>(
+    file_id: u32,
+    reader: &mut R,
+    len: u64,
+    order: Endianness,
+    now: u64,
+) -> Result<(KeyDir, RecoveryInfo)> {
+    let mut keydir = KeyDir::new();
+    let mut removed = HashMap::new();
+    let mut recovery = RecoveryInfo::default();
+    let mut entry_pos = FileHeader::SIZE as u64;
+    reader.seek(SeekFrom::Start(entry_pos))?;
+
+    while entry_pos < len {
+        let entry = match KeyValueEntry::read_indexed(reader, order) {
+            Ok(entry) if entry_pos + entry.encoded_size() as u64 <= len => entry,
+            Ok(_) | Err(CaskaError::Truncated) => {
+                recovery.bytes_truncated = (len - entry_pos) as usize;
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        recovery.entries_loaded += 1;
+        recovery.last_seq = recovery.last_seq.max(entry.seq);
+        let next_pos = entry_pos + entry.encoded_size() as u64;
+        apply_entry(
+            file_id,
+            entry_pos as usize,
+            entry,
+            now,
+            &mut keydir,
+            &mut removed,
+        );
+        entry_pos = next_pos;
+    }
+
+    Ok((keydir, recovery))
+}
+
+/// The latest tombstone of each key `keydir` no longer holds, among
+/// `entries` younger than `tombstone_ttl`, for a merge to carry over.
+fn retained_tombstones(
+    entries: impl Iterator<Item = Result<KeyValueEntry>>,
+    keydir: &KeyDir,
+    options: &Options,
+    now: u64,
+) -> Result<BTreeMap<Vec<u8>, KeyValueEntry>> {
+    let mut tombstones = BTreeMap::new();
+    if options.tombstone_ttl.is_zero() {
+        return Ok(tombstones);
+    }
+
+    let cutoff = now.saturating_sub(options.tombstone_ttl.as_secs());
+    for entry in entries {
+        let entry = entry?;
+        if entry.is_tombstone() && entry.tstamp > cutoff && !keydir.contains_key(&entry.key) {
+            tombstones.insert(entry.key.clone(), entry);
         }
     }
+    Ok(tombstones)
+}
 
-    fn get<K: ToBytes>(&self, key: K) -> Option<Vec<u8>> {
-        let entry = self.keydir.get(&key.to_bytes())?;
-        let offset = entry.value_pos as usize;
-        let size = entry.value_sz as usize;
-        self.file
-            .data
-            .get(offset..offset + size)
-            .map(<[u8]>::to_vec)
+/// Folds one entry read at `entry_pos` into `keydir`, unless a newer
+/// version of its key has been seen already.
+fn apply_entry(
+    file_id: u32,
+    entry_pos: usize,
+    entry: KeyValueEntry,
+    now: u64,
+    keydir: &mut KeyDir,
+    removed: &mut HashMap<Vec<u8>, (u64, u64)>,
+) {
+    let keydir_entry = KeyDirEntry::new(file_id, entry_pos, &entry);
+    let newest = keydir
+        .get(&entry.key)
+        .map(KeyDirEntry::version)
+        .max(removed.get(&entry.key).copied());
+    if newest.is_some_and(|version| version > keydir_entry.version()) {
+        return;
     }
 
-    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
-    where
-        K: ToBytes,
-        V: ToBytes,
+    if entry.is_tombstone() || keydir_entry.is_expired(now) {
+        keydir.remove(&entry.key);
+        removed.insert(entry.key, keydir_entry.version());
+        return;
+    }
+
+    removed.remove(&entry.key);
+    keydir.insert(entry.key, keydir_entry);
+}
+
+/// Writes the length of the data file the hint describes and the store's
+/// last `seq`, then one record per live key: `tstamp`, `seq`, `expiry`,
+/// `flags`, `ksz`, `value_sz`, `value_pos` and the key bytes. A CRC32 of
+/// everything before it closes the hint, so one cut short is caught rather
+/// than loaded as a smaller keydir. Hints describe a single data file.
+///
+/// `last_seq` is kept apart from the records because the newest write may
+/// be a tombstone, which the keydir doesn't hold.
+fn write_hint<W: Write>(
+    keydir: &KeyDir,
+    data_len: usize,
+    last_seq: u64,
+    w: &mut W,
+    order: Endianness,
+) -> Result<()> {
+    let mut hint = vec![];
+    order.write_u64(&mut hint, data_len as u64)?;
+    order.write_u64(&mut hint, last_seq)?;
+    for (key, entry) in keydir {
+        order.write_u64(&mut hint, entry.tstamp)?;
+        order.write_u64(&mut hint, entry.seq)?;
+        order.write_u64(&mut hint, entry.expiry)?;
+        hint.write_u8(entry.flags)?;
+        order.write_u32(&mut hint, key.len() as u32)?;
+        order.write_u32(&mut hint, entry.value_sz)?;
+        order.write_u32(&mut hint, entry.value_pos)?;
+        hint.write_all(key)?;
+    }
+    let crc = crc32fast::hash(&hint);
+    order.write_u32(&mut hint, crc)?;
+    w.write_all(&hint)?;
+    Ok(())
+}
+
+/// Replaces `path` with `data` so a crash leaves the old contents or the
+/// new, never part of either: `data` is synced to a temporary file beside
+/// `path` and then renamed over it.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut tmp = fs::File::create(&tmp_path)?;
+    tmp.write_all(data)?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path)
+}
+
+/// Syncs the directory holding `path`, so a rename or removal there
+/// survives a crash along with the files' contents.
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    // Windows can't open a directory as a file, and doesn't need to.
+    #[cfg(unix)]
     {
-        let entry = KeyValueEntry::new(now(), key, value);
-        let value_pos = self.file.size() + entry.value_offset();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Rebuilds a keydir and the last `seq` from a hint file without touching
+/// the data file. Returns `None` if the hint fails its checksum, or if the
+/// data file is no longer `data_len` bytes long and the hint is stale;
+/// either way the caller should scan the data file instead.
+fn load_hint(
+    file_id: u32,
+    hint: &[u8],
+    data_len: usize,
+    order: Endianness,
+) -> Result<Option<(KeyDir, u64)>> {
+    let Some(body_len) = hint.len().checked_sub(size_of::<u32>()) else {
+        return Ok(None);
+    };
+    let (hint, mut crc) = hint.split_at(body_len);
+    if order.read_u32(&mut crc)? != crc32fast::hash(hint) {
+        return Ok(None);
+    }
+
+    let mut keydir = KeyDir::new();
+    let mut cursor = Cursor::new(hint);
+    if order.read_u64(&mut cursor)? != data_len as u64 {
+        return Ok(None);
+    }
+    let last_seq = order.read_u64(&mut cursor)?;
+
+    while (cursor.position() as usize) < hint.len() {
+        let tstamp = order.read_u64(&mut cursor)?;
+        let seq = order.read_u64(&mut cursor)?;
+        let expiry = order.read_u64(&mut cursor)?;
+        let flags = cursor.read_u8()?;
+        let ksz = order.read_u32(&mut cursor)?;
+        let value_sz = order.read_u32(&mut cursor)?;
+        let value_pos = order.read_u32(&mut cursor)?;
+        let key = read_bytes(&mut cursor, ksz)?;
 
         let keydir_entry = KeyDirEntry {
-            file_id: self.file.id,
-            value_sz: entry.value_sz,
-            value_pos: value_pos as u32,
-            tstamp: entry.tstamp,
+            file_id,
+            value_sz,
+            value_pos,
+            tstamp,
+            seq,
+            expiry,
+            flags,
         };
-        self.keydir.insert(entry.key.clone(), keydir_entry);
+        keydir.insert(key, keydir_entry);
+    }
 
-        let entry_data: Vec<u8> = entry.try_into()?;
-        self.file.append(&entry_data);
+    Ok(Some((keydir, last_seq)))
+}
 
-        Ok(())
-    }
+#[derive(Debug, PartialEq)]
+struct EntryMeta {
+    value: Vec<u8>,
+    tstamp: u64,
 }
 
-fn now() -> u32 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StoreStats {
+    live_keys: usize,
+    /// Encoded size of the entries the keydir still points at.
+    live_bytes: usize,
+    /// Size of every file, including superseded entries and tombstones.
+    total_bytes: usize,
+    /// Share of `total_bytes` that a merge would reclaim.
+    fragmentation_ratio: f64,
+    /// Bytes appended by puts over the life of the handle, across rotations
+    /// and merges.
+    total_bytes_written: usize,
+    /// Bytes freed by every merge over the life of the handle.
+    total_bytes_reclaimed: usize,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// What a merge did, measured from the files it read and wrote.
+#[derive(Debug, Default, PartialEq)]
+struct MergeReport {
+    files_merged: usize,
+    /// Entries of every kind in the files that were merged.
+    entries_before: usize,
+    /// Live entries and kept tombstones in the merged file.
+    entries_after: usize,
+    bytes_before: usize,
+    bytes_after: usize,
+    reclaimed_bytes: usize,
+    elapsed: Duration,
+}
 
-    #[test]
-    fn encode_a_key_value_entry() {
-        let entry = KeyValueEntry::new(42, 42, 42);
-        let got: Vec<u8> = entry.try_into().unwrap();
-        let want = vec![
-            0, 0, 0, 42, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 42, 0, 0, 0, 42,
-        ];
-        assert_eq!(want, got);
+/// A merged file and keydir from [`MemoryStore::prepare_merge`], not yet
+/// swapped in.
+#[derive(Debug)]
+struct PreparedMerge {
+    file: File,
+    keydir: KeyDir,
+    /// Active file it was built from and how much of it was covered, so
+    /// [`MemoryStore::catch_up_merge`] knows where later writes start.
+    source_id: u32,
+    source_len: usize,
+    files_merged: usize,
+    entries_before: usize,
+    started: Instant,
+}
+
+/// Picks immutable files worth merging with [`MemoryStore::merge_files`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MergePolicy {
+    /// Files at least this fragmented are candidates.
+    min_fragmentation: f64,
+}
+
+impl MergePolicy {
+    fn new(min_fragmentation: f64) -> Self {
+        Self { min_fragmentation }
     }
 
-    #[test]
-    fn decode_a_key_value_entry() {
-        let bytes = vec![
-            0, 0, 0, 42, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 42, 0, 0, 0, 42,
-        ];
-        let want = KeyValueEntry::new(42, 42, 42);
-        let got = bytes.try_into().unwrap();
-        assert_eq!(want, got);
+    /// Ids of the files whose fragmentation ratio reaches the threshold.
+    fn candidates(&self, store: &MemoryStore) -> Vec<u32> {
+        store
+            .file_fragmentation()
+            .into_iter()
+            .filter(|&(_, ratio)| ratio >= self.min_fragmentation)
+            .map(|(file_id, _)| file_id)
+            .collect()
     }
+}
 
-    #[test]
-    fn memory_store_can_set_and_get_a_key_value_pair() {
-        let mut store = MemoryStore::new();
+/// Outcome of checking every entry's checksum with [`MemoryStore::verify`].
+#[derive(Debug, Default, PartialEq)]
+struct VerifyReport {
+    entries_ok: usize,
+    entries_corrupt: usize,
+    /// Where each failing entry starts, as `(file_id, byte offset)`.
+    corrupt_offsets: Vec<(u32, usize)>,
+}
 
-        let key_value_pairs = vec![
-            ("hello", "world"),
-            ("first_name", "john"),
-            ("last_name", "smith"),
-        ];
-        for (key, value) in key_value_pairs {
-            store.put(key, value).unwrap();
+/// A point-in-time copy of the live keys and the bytes they point at, so
+/// later writes to the store don't show up while iterating. Immutable files
+/// are shared with the store; only the active one is copied.
+#[derive(Debug)]
+struct Snapshot {
+    keydir: KeyDir,
+    files: HashMap<u32, Arc<File>>,
+}
 
-            let want = value;
-            let got = store.get(key).unwrap();
+impl Snapshot {
+    fn iter(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.keydir.iter().filter_map(|(key, entry)| {
+            let stored = self.files.get(&entry.file_id)?.stored_value(entry)?;
+            let value = compression::decompress(entry.flags, stored).ok()?;
+            Some((key.clone(), value.into_owned()))
+        })
+    }
+}
+
+/// Most bytes [`MemoryStore::read_value_streaming`] hands to a writer at once.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Marks the start of an [`MemoryStore::export`] stream.
+const EXPORT_MAGIC: [u8; 4] = *b"CSKX";
+
+/// On-disk format version written into every file header. Files from any
+/// other version are refused rather than misparsed.
+const FORMAT_VERSION: u16 = 5;
+
+// The version stands for one exact entry layout: changing the fixed fields
+// without bumping it fails the build here.
+const _: () = assert!(
+    FORMAT_VERSION == 5 && KeyValueEntry::HEADER_SIZE == 37,
+    "entry layout changed; bump FORMAT_VERSION"
+);
+
+/// Identifies a caska data file, the format version it was written with,
+/// and the byte order and checksum algorithm of its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileHeader {
+    version: u16,
+    byte_order: Endianness,
+    checksum: ChecksumKind,
+}
+
+impl FileHeader {
+    const MAGIC: [u8; 4] = *b"CASK";
+    /// Magic, a big-endian version, then the byte order and checksum tags.
+    const SIZE: usize = Self::MAGIC.len() + size_of::<u16>() + 2 * size_of::<u8>();
+
+    fn new(byte_order: Endianness, checksum: ChecksumKind) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            byte_order,
+            checksum,
+        }
+    }
+
+    fn encode(self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::SIZE);
+        buf.extend_from_slice(&Self::MAGIC);
+        buf.extend_from_slice(&self.version.to_be_bytes());
+        buf.push(self.byte_order.tag());
+        buf.push(self.checksum.tag());
+        buf
+    }
+
+    fn check_byte_order(self, expected: Endianness) -> Result<Self> {
+        if self.byte_order != expected {
+            return Err(CaskaError::ByteOrderMismatch {
+                found: self.byte_order,
+                expected,
+            });
+        }
+        Ok(self)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < Self::SIZE || data[..Self::MAGIC.len()] != Self::MAGIC {
+            return Err(CaskaError::InvalidMagic);
+        }
+
+        let version = BigEndian::read_u16(&data[Self::MAGIC.len()..]);
+        if version != FORMAT_VERSION {
+            return Err(CaskaError::UnsupportedVersion {
+                found: version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        let byte_order =
+            Endianness::from_tag(data[Self::SIZE - 2]).ok_or(CaskaError::InvalidMagic)?;
+        let checksum =
+            ChecksumKind::from_tag(data[Self::SIZE - 1]).ok_or(CaskaError::InvalidMagic)?;
+
+        Ok(Self {
+            version,
+            byte_order,
+            checksum,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct File {
+    id: u32,
+    byte_order: Endianness,
+    checksum: ChecksumKind,
+    data: Vec<u8>,
+}
+
+impl File {
+    fn new(id: u32, byte_order: Endianness, checksum: ChecksumKind) -> Self {
+        Self {
+            id,
+            byte_order,
+            checksum,
+            data: FileHeader::new(byte_order, checksum).encode(),
+        }
+    }
+
+    /// A file with the same byte order and checksum, for rotation and
+    /// rewrites.
+    fn successor(&self) -> Self {
+        Self::new(self.id + 1, self.byte_order, self.checksum)
+    }
+
+    /// Encodes `entry` the way this file's header says entries are stored.
+    fn encode(&self, entry: KeyValueEntry) -> Result<Vec<u8>> {
+        entry.encode(self.byte_order, self.checksum)
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size() <= FileHeader::SIZE
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+
+    /// The value bytes `entry` points at, exactly as stored.
+    fn stored_value(&self, entry: &KeyDirEntry) -> Option<&[u8]> {
+        let offset = entry.value_pos as usize;
+        let size = entry.value_sz as usize;
+        self.data.get(offset..offset + size)
+    }
+
+    /// Number of entries up to the first one that can't be decoded.
+    fn entry_count(&self) -> usize {
+        self.entries().take_while(Result::is_ok).count()
+    }
+
+    fn entries(&self) -> Entries<'_> {
+        let mut cursor = Cursor::new(self.data.as_slice());
+        cursor.set_position(FileHeader::SIZE as u64);
+        Entries {
+            cursor,
+            byte_order: self.byte_order,
+            checksum: self.checksum,
+            failed: false,
+        }
+    }
+}
+
+/// Decodes the entries of a file one by one, stopping after the first error.
+struct Entries<'a> {
+    cursor: Cursor<&'a [u8]>,
+    byte_order: Endianness,
+    checksum: ChecksumKind,
+    failed: bool,
+}
+
+impl Iterator for Entries<'_> {
+    type Item = Result<KeyValueEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.cursor.position() as usize >= self.cursor.get_ref().len() {
+            return None;
+        }
+
+        let entry = KeyValueEntry::read_from(&mut self.cursor, self.byte_order, self.checksum);
+        self.failed = entry.is_err();
+        Some(entry)
+    }
+}
+
+#[derive(Debug)]
+struct MemoryStore {
+    file: File,
+    /// Files that are full and never written again, shared with snapshots.
+    files: HashMap<u32, Arc<File>>,
+    keydir: KeyDir,
+    bloom: Option<BloomFilter>,
+    /// Access order for `max_entries` eviction. Reads update it through
+    /// `&self`, hence the lock.
+    lru: Option<Mutex<Lru>>,
+    /// Sequence number of the most recent write.
+    seq: u64,
+    bytes_written: usize,
+    bytes_reclaimed: usize,
+    options: Options,
+}
+
+impl MemoryStore {
+    fn new() -> Self {
+        Self::with_options(Options::default())
+    }
+
+    fn with_options(options: Options) -> Self {
+        Self {
+            file: File::new(
+                options.clock.now() as u32,
+                options.byte_order,
+                options.checksum,
+            ),
+            files: HashMap::new(),
+            keydir: KeyDir::new(),
+            bloom: options.bloom_filter_keys.map(BloomFilter::new),
+            lru: options.max_entries.map(|_| Mutex::default()),
+            seq: 0,
+            bytes_written: 0,
+            bytes_reclaimed: 0,
+            options,
+        }
+    }
+
+    fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        Self::from_bytes_with(data, Options::default())
+    }
+
+    fn from_bytes_with(data: Vec<u8>, options: Options) -> Result<Self> {
+        Self::recover_from_bytes(data, options).map(|(store, _)| store)
+    }
+
+    /// Like `from_bytes_with`, but also reports any partially written entry
+    /// that was discarded from the end of `data`.
+    fn recover_from_bytes(mut data: Vec<u8>, options: Options) -> Result<(Self, RecoveryInfo)> {
+        let mut store = Self::with_options(options);
+        let (keydir, recovery) =
+            load_keydir(store.file.id, &data, store.options.byte_order, store.now())?;
+        store.file.checksum = FileHeader::decode(&data)?.checksum;
+        data.truncate(data.len() - recovery.bytes_truncated);
+        store.emit_repair(recovery);
+        store.keydir = keydir;
+        store.file.data = data;
+        store.seq = recovery.last_seq;
+        store.rebuild_bloom();
+        store.rebuild_lru();
+
+        Ok((store, recovery))
+    }
+
+    /// Builds a store from `items` in one pass, much faster than calling
+    /// `put` for each: the keydir, Bloom filter and eviction order are set
+    /// up once at the end and no events are emitted. Later pairs win over
+    /// earlier ones with the same key, as with `put`.
+    fn bulk_load<K, V, I>(items: I, options: Options) -> Result<Self>
+    where
+        K: ToBytes,
+        V: ToBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut store = Self::with_options(options);
+        let items = items.into_iter();
+        store
+            .file
+            .data
+            .reserve(items.size_hint().0 * KeyValueEntry::HEADER_SIZE);
+
+        let tstamp = store.now();
+        for (key, value) in items {
+            let entry = KeyValueEntry::new(tstamp, key, value);
+            entry.check_size(&store.options)?;
+            let entry = entry
+                .compress(store.options.compression)?
+                .with_seq(store.next_seq());
+            store.rotate_if_needed(entry.size());
+
+            let keydir_entry = KeyDirEntry::new(store.file.id, store.file.size(), &entry);
+            store.keydir.insert(entry.key.clone(), keydir_entry);
+            let entry_data = store.file.encode(entry)?;
+            store.bytes_written += entry_data.len();
+            store.file.append(&entry_data);
+        }
+        store.rebuild_bloom();
+        store.rebuild_lru();
+        store.evict_if_needed()?;
+
+        Ok(store)
+    }
+
+    /// Loads `data` using the keydir saved in `hint_path`, falling back to a
+    /// full scan when the hint was written for a different length of data.
+    fn load_from_hint(data: Vec<u8>, hint_path: impl AsRef<Path>) -> Result<Self> {
+        let header = FileHeader::decode(&data)?;
+        let options = Options::new()
+            .byte_order(header.byte_order)
+            .checksum(header.checksum);
+        Self::load_from_hint_with(data, hint_path, options)
+    }
+
+    /// Like `load_from_hint`, but with the caller's options, whose byte
+    /// order must match the data's.
+    fn load_from_hint_with(
+        data: Vec<u8>,
+        hint_path: impl AsRef<Path>,
+        options: Options,
+    ) -> Result<Self> {
+        let header = FileHeader::decode(&data)?.check_byte_order(options.byte_order)?;
+        let hint = fs::read(hint_path)?;
+        let mut store = Self::with_options(options);
+        let Some((keydir, last_seq)) =
+            load_hint(store.file.id, &hint, data.len(), header.byte_order)?
+        else {
+            return Self::from_bytes_with(data, store.options);
+        };
+        store.file.checksum = header.checksum;
+        store.keydir = keydir;
+        store.file.data = data;
+        store.seq = last_seq;
+        store.rebuild_bloom();
+        store.rebuild_lru();
+
+        Ok(store)
+    }
+
+    /// Writes every live pair as `EXPORT_MAGIC`, a `u64` pair count, then
+    /// `ksz`, `value_sz`, key and value per pair. Superseded entries,
+    /// tombstones and expiry times aren't included.
+    fn export<W: Write>(&self, w: &mut W) -> Result<()> {
+        w.write_all(&EXPORT_MAGIC)?;
+        w.write_u64::<BigEndian>(self.live_entries().count() as u64)?;
+        for (key, entry) in self.live_entries() {
+            let value = self.try_value(entry)?;
+            w.write_u32::<BigEndian>(key.len() as u32)?;
+            w.write_u32::<BigEndian>(value.len() as u32)?;
+            w.write_all(key)?;
+            w.write_all(&value)?;
+        }
+        Ok(())
+    }
+
+    /// Builds a fresh store from a stream written by [`MemoryStore::export`].
+    fn import<R: Read>(r: &mut R) -> Result<Self> {
+        Self::import_with(r, Options::default())
+    }
+
+    /// Like `import`, but into a store with the given options. Lengths in
+    /// the stream are checked against the options' size limits before
+    /// anything is read, and never allocated up front.
+    fn import_with<R: Read>(r: &mut R, options: Options) -> Result<Self> {
+        let mut magic = [0; EXPORT_MAGIC.len()];
+        r.read_exact(&mut magic)?;
+        if magic != EXPORT_MAGIC {
+            return Err(CaskaError::InvalidMagic);
+        }
+
+        let mut store = Self::with_options(options);
+        let count = r.read_u64::<BigEndian>()?;
+        for _ in 0..count {
+            let ksz = r.read_u32::<BigEndian>()?;
+            let value_sz = r.read_u32::<BigEndian>()?;
+            if ksz as usize > store.options.max_key_size {
+                return Err(CaskaError::KeyTooLarge);
+            }
+            if value_sz as usize > store.options.max_value_size {
+                return Err(CaskaError::ValueTooLarge);
+            }
+            let key = read_bytes(r, ksz)?;
+            let value = read_bytes(r, value_sz)?;
+            store.put(key, value)?;
+        }
+
+        Ok(store)
+    }
+
+    fn write_hint_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        if !self.files.is_empty() {
+            return Err(CaskaError::Unsupported(
+                "hint files cover a single data file, merge first",
+            ));
+        }
+
+        let mut hint = vec![];
+        write_hint(
+            &self.keydir,
+            self.file.size(),
+            self.seq,
+            &mut hint,
+            self.options.byte_order,
+        )?;
+        write_atomically(path.as_ref(), &hint)?;
+
+        Ok(())
+    }
+
+    fn get<K: ToBytes>(&self, key: K) -> Option<Vec<u8>> {
+        let entry = self.lookup(&key.to_bytes())?;
+        self.value(entry).map(Cow::into_owned)
+    }
+
+    /// Like `get`, but borrows the value from the data file unless it has
+    /// to be decompressed.
+    fn get_cow<K: ToBytes>(&self, key: K) -> Option<Cow<'_, [u8]>> {
+        let entry = self.lookup(&key.to_bytes())?;
+        self.value(entry)
+    }
+
+    /// Like `get`, but copies the value into `buf` so it can be reused
+    /// across reads. Returns the value's length, or `None` if absent.
+    fn get_into<K: ToBytes>(&self, key: K, buf: &mut Vec<u8>) -> Option<usize> {
+        let entry = self.lookup(&key.to_bytes())?;
+        let value = self.value(entry)?;
+        buf.clear();
+        buf.extend_from_slice(&value);
+        Some(value.len())
+    }
+
+    /// Copies a value to `out` in `STREAM_CHUNK_SIZE` pieces straight from
+    /// the data file, returning the number of bytes written. Compressed
+    /// values have to be decompressed in full first.
+    fn read_value_streaming<K: ToBytes, W: Write>(
+        &self,
+        key: K,
+        out: &mut W,
+    ) -> Result<Option<u64>> {
+        let Some(entry) = self.lookup(&key.to_bytes()) else {
+            return Ok(None);
+        };
+        let value = self.try_value(entry)?;
+        for chunk in value.chunks(STREAM_CHUNK_SIZE) {
+            out.write_all(chunk)?;
+        }
+        Ok(Some(value.len() as u64))
+    }
+
+    /// Looks up each key in turn; results line up with the input order.
+    fn get_many<K: ToBytes>(&self, keys: impl IntoIterator<Item = K>) -> Vec<Option<Vec<u8>>> {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
+    fn get_entry<K: ToBytes>(&self, key: K) -> Option<EntryMeta> {
+        let entry = self.lookup(&key.to_bytes())?;
+        Some(EntryMeta {
+            value: self.value(entry)?.into_owned(),
+            tstamp: entry.tstamp,
+        })
+    }
+
+    fn get_as<K: ToBytes, T: FromBytes>(&self, key: K) -> Result<Option<T>> {
+        let Some(entry) = self.lookup(&key.to_bytes()) else {
+            return Ok(None);
+        };
+        T::from_bytes(&self.try_value(entry)?).map(Some)
+    }
+
+    /// Looks up a key on behalf of a reader, counting it as a use for
+    /// eviction.
+    fn lookup(&self, key: &[u8]) -> Option<&KeyDirEntry> {
+        let entry = self.live_entry(key)?;
+        if let Some(lru) = &self.lru {
+            lru.lock().expect("lru lock poisoned").touch(key);
+        }
+        Some(entry)
+    }
+
+    /// Looks up a key, treating expired entries as absent.
+    fn live_entry(&self, key: &[u8]) -> Option<&KeyDirEntry> {
+        if self
+            .bloom
+            .as_ref()
+            .is_some_and(|bloom| !bloom.may_contain(key))
+        {
+            return None;
+        }
+        self.keydir
+            .get(key)
+            .filter(|entry| !entry.is_expired(self.now()))
+    }
+
+    /// Returns the value as it was written, decompressing it if needed.
+    fn value(&self, entry: &KeyDirEntry) -> Option<Cow<'_, [u8]>> {
+        self.try_value(entry).ok()
+    }
+
+    fn try_value(&self, entry: &KeyDirEntry) -> Result<Cow<'_, [u8]>> {
+        let stored = self.raw_value(entry).ok_or_else(|| missing_value(entry))?;
+        compression::decompress(entry.flags, stored)
+    }
+
+    /// Returns the value bytes exactly as stored in the data file.
+    fn raw_value(&self, entry: &KeyDirEntry) -> Option<&[u8]> {
+        #[cfg(test)]
+        testing::count_value_read();
+        self.file(entry.file_id)?.stored_value(entry)
+    }
+
+    fn file(&self, id: u32) -> Option<&File> {
+        if id == self.file.id {
+            Some(&self.file)
+        } else {
+            self.files.get(&id).map(Arc::as_ref)
+        }
+    }
+
+    /// Stored size of a live value, straight from the keydir. For compressed
+    /// values this is the compressed size.
+    fn value_size<K: ToBytes>(&self, key: K) -> Option<u32> {
+        self.live_entry(&key.to_bytes()).map(|entry| entry.value_sz)
+    }
+
+    /// `(key, file_id, value_pos, value_sz)` for every live key, in key
+    /// order, for tools that read the data files themselves. Positions count
+    /// from the start of the file and cover the value as stored, i.e. after
+    /// compression.
+    fn dump_index(&self) -> Vec<(Vec<u8>, u32, u32, u32)> {
+        self.live_entries()
+            .map(|(key, entry)| (key.to_vec(), entry.file_id, entry.value_pos, entry.value_sz))
+            .collect()
+    }
+
+    fn contains_key<K: ToBytes>(&self, key: K) -> bool {
+        self.live_entry(&key.to_bytes()).is_some()
+    }
+
+    /// Number of live keys. Expired keys stay in the keydir until the next
+    /// merge, so this walks it rather than trusting its length.
+    fn len(&self) -> usize {
+        self.live_entries().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.live_entries().next().is_none()
+    }
+
+    /// Id of the file new writes go to; rotation always moves to a higher
+    /// one.
+    fn active_file_id(&self) -> u32 {
+        self.file.id
+    }
+
+    /// Number of data files, counting the active one.
+    fn file_count(&self) -> usize {
+        self.files.len() + 1
+    }
+
+    /// Counts live keys satisfying `pred` without reading any values.
+    fn count_matching<F: Fn(&[u8]) -> bool>(&self, pred: F) -> usize {
+        self.live_entries().filter(|(key, _)| pred(key)).count()
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.live_entries().map(|(key, _)| key)
+    }
+
+    fn values(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.live_entries()
+            .filter_map(|(_, entry)| self.value(entry).map(Cow::into_owned))
+    }
+
+    /// Reduces over every live key/value pair in key order without
+    /// collecting them.
+    fn fold<B, F>(&self, init: B, mut f: F) -> B
+    where
+        F: FnMut(B, &[u8], &[u8]) -> B,
+    {
+        self.live_entries()
+            .fold(init, |acc, (key, entry)| match self.value(entry) {
+                Some(value) => f(acc, key, &value),
+                None => acc,
+            })
+    }
+
+    fn live_entries(&self) -> impl Iterator<Item = (&[u8], &KeyDirEntry)> {
+        let now = self.now();
+        self.keydir
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key.as_slice(), entry))
+    }
+
+    /// Yields every live key starting with `prefix` along with its value, in
+    /// ascending key order.
+    fn scan_prefix<'a>(&'a self, prefix: &[u8]) -> impl Iterator<Item = (&'a [u8], Vec<u8>)> {
+        let prefix = prefix.to_vec();
+        let now = self.now();
+        self.keydir
+            .range(prefix.clone()..)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .filter_map(|(key, entry)| Some((key.as_slice(), self.value(entry)?.into_owned())))
+    }
+
+    /// Live keys starting with `prefix`, sorted, without reading any values.
+    fn keys_with_prefix(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let now = self.now();
+        self.keydir
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Yields live pairs whose keys fall within `range`, in ascending key order.
+    fn scan_range<R: RangeBounds<Vec<u8>>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        let now = self.now();
+        self.keydir
+            .range(range)
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .filter_map(|(key, entry)| Some((key.clone(), self.value(entry)?.into_owned())))
+    }
+
+    /// Yields live pairs whose `u64` keys fall within `[start, end)`, in
+    /// numeric order. Big-endian keys sort the same as the numbers they
+    /// encode; keys that aren't 8 bytes long are skipped. An empty or
+    /// inverted range yields nothing.
+    fn scan_numeric_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> impl Iterator<Item = (u64, Vec<u8>)> + '_ {
+        // `BTreeMap::range` panics on an inverted range.
+        let end = end.max(start);
+        self.scan_range(start.to_bytes()..end.to_bytes())
+            .filter_map(|(key, value)| Some((u64::from_bytes(&key).ok()?, value)))
+    }
+
+    /// Like `scan_range`, but in descending key order.
+    fn scan_range_rev<R: RangeBounds<Vec<u8>>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        let now = self.now();
+        self.keydir
+            .range(range)
+            .rev()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .filter_map(|(key, entry)| Some((key.clone(), self.value(entry)?.into_owned())))
+    }
+
+    /// Yields every live pair in ascending key order. Values are read one at
+    /// a time as the iterator advances, so stopping early skips the rest.
+    fn scan_all(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.scan_range(..)
+    }
+
+    /// Yields every live pair in descending key order.
+    fn iter_rev(&self) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
+        self.scan_range_rev(..)
+    }
+
+    /// The smallest live key in byte order.
+    fn first_key(&self) -> Option<Vec<u8>> {
+        self.live_entries().next().map(|(key, _)| key.to_vec())
+    }
+
+    /// The largest live key in byte order.
+    fn last_key(&self) -> Option<Vec<u8>> {
+        let now = self.now();
+        self.keydir
+            .iter()
+            .rev()
+            .find(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Returns up to `limit` live pairs with keys strictly after `after`, in
+    /// key order, plus the cursor to resume from, or `None` once exhausted.
+    /// A `limit` of 0 is refused, since no cursor could say where to resume.
+    fn scan_page(&self, after: Option<&[u8]>, limit: usize) -> Result<Page> {
+        if limit == 0 {
+            return Err(CaskaError::Unsupported(
+                "scan_page needs a limit of at least 1",
+            ));
+        }
+        let start = match after {
+            Some(key) => Bound::Excluded(key.to_vec()),
+            None => Bound::Unbounded,
+        };
+        let mut pairs = self.scan_range((start, Bound::Unbounded));
+
+        let page: Vec<_> = pairs.by_ref().take(limit).collect();
+        let cursor = match pairs.next() {
+            Some(_) => page.last().map(|(key, _)| key.clone()),
+            None => None,
+        };
+        Ok((page, cursor))
+    }
+
+    /// Decodes every physical entry, including superseded ones and
+    /// tombstones, oldest file first.
+    fn iter_entries(&self) -> impl Iterator<Item = Result<KeyValueEntry>> + '_ {
+        self.files_by_id().flat_map(File::entries)
+    }
+
+    /// `(tstamp, value)` for every physical version of `key` still on disk,
+    /// in write order, for debugging. Tombstones are skipped, and a merge
+    /// leaves at most one version. Stops at the first undecodable entry.
+    fn get_versions<K: ToBytes>(&self, key: K) -> Vec<(u64, Vec<u8>)> {
+        let key = key.to_bytes();
+        self.iter_entries()
+            .map_while(Result::ok)
+            .filter(|entry| entry.key == key && !entry.is_tombstone())
+            .filter_map(|entry| {
+                let value = compression::decompress(entry.flags, &entry.value).ok()?;
+                Some((entry.tstamp, value.into_owned()))
+            })
+            .collect()
+    }
+
+    /// Every file, oldest first, ending with the active one.
+    fn files_by_id(&self) -> impl Iterator<Item = &File> {
+        let mut files: Vec<&File> = self.files.values().map(Arc::as_ref).collect();
+        files.sort_by_key(|file| file.id);
+        files.into_iter().chain(iter::once(&self.file))
+    }
+
+    fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        self.write(KeyValueEntry::new(self.now(), key, value))?;
+        Ok(())
+    }
+
+    /// Like `put`, but returns where the value landed so callers can keep
+    /// their own index of byte positions. `value_pos` and `value_sz` cover
+    /// the value as stored, i.e. after compression.
+    fn put_returning_offset<K, V>(&mut self, key: K, value: V) -> Result<KeyDirEntry>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        self.write(KeyValueEntry::new(self.now(), key, value))
+    }
+
+    /// Like `put`, but returns the value previously stored under `key`.
+    fn insert<K, V>(&mut self, key: K, value: V) -> Result<Option<Vec<u8>>>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        let key = key.to_bytes();
+        let previous = self.get(key.as_slice());
+        self.put(key, value)?;
+        Ok(previous)
+    }
+
+    fn put_with_ttl<K, V>(&mut self, key: K, value: V, ttl: Duration) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        let tstamp = self.now();
+        // A ttl too long to represent never expires instead of wrapping
+        // into the past.
+        let expiry = tstamp.saturating_add(ttl.as_secs());
+        let entry = KeyValueEntry::new(tstamp, key, value).with_expiry(expiry);
+        self.write(entry)?;
+        Ok(())
+    }
+
+    /// Writes `new` only if the current value equals `expected`, where `None`
+    /// means the key must be absent. Returns whether the swap happened.
+    fn compare_and_swap<K, V>(&mut self, key: K, expected: Option<&[u8]>, new: V) -> Result<bool>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        let key = key.to_bytes();
+        let current = self.live_entry(&key).and_then(|entry| self.value(entry));
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+
+        self.put(key, new)?;
+        Ok(true)
+    }
+
+    /// Adds `delta` to a big-endian `i64` counter, wrapping on overflow.
+    /// Absent keys start from 0.
+    fn increment<K: ToBytes>(&mut self, key: K, delta: i64) -> Result<i64> {
+        let key = key.to_bytes();
+        let current = match self.live_entry(&key).map(|entry| self.try_value(entry)) {
+            Some(bytes) => {
+                let bytes = bytes?;
+                let bytes: [u8; 8] = bytes.as_ref().try_into().map_err(|_| {
+                    CaskaError::InvalidValue(format!(
+                        "expected 8 bytes for i64, got {}",
+                        bytes.len()
+                    ))
+                })?;
+                i64::from_be_bytes(bytes)
+            }
+            None => 0,
+        };
+
+        let value = current.wrapping_add(delta);
+        self.put(key, value)?;
+        Ok(value)
+    }
+
+    /// Combines the current value (`None` if absent) with `operand` using `f`
+    /// and stores the result.
+    fn merge_value<K, V, F>(&mut self, key: K, operand: V, f: F) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+        F: Fn(Option<&[u8]>, &[u8]) -> Vec<u8>,
+    {
+        let key = key.to_bytes();
+        let current = self
+            .live_entry(&key)
+            .map(|entry| self.try_value(entry))
+            .transpose()?;
+        let value = f(current.as_deref(), &operand.to_bytes());
+
+        self.put(key, value)
+    }
+
+    /// Hands the current value to `f` to change in place and writes back
+    /// whatever it leaves, keeping the key's expiry. Returns `false`, without
+    /// calling `f`, if the key is absent.
+    fn modify<K: ToBytes, F: FnOnce(&mut Vec<u8>)>(&mut self, key: K, f: F) -> Result<bool> {
+        let key = key.to_bytes();
+        let Some(entry) = self.live_entry(&key) else {
+            return Ok(false);
+        };
+
+        let expiry = entry.expiry;
+        let mut value = self.try_value(entry)?.into_owned();
+        f(&mut value);
+        self.write(KeyValueEntry::new(self.now(), key, value).with_expiry(expiry))?;
+        Ok(true)
+    }
+
+    /// Moves the value under `from` to `to`, replacing anything already
+    /// there and keeping its expiry. Returns `false` if `from` is absent.
+    ///
+    /// The copy and `from`'s tombstone are appended as one batch, and the
+    /// cache limit is only enforced once both have landed. The copy comes
+    /// first, so a crash that tears the batch leaves the value under both
+    /// keys rather than neither.
+    fn rename_key<K: ToBytes>(&mut self, from: K, to: K) -> Result<bool> {
+        let (from, to) = (from.to_bytes(), to.to_bytes());
+        let Some(keydir_entry) = self.live_entry(&from) else {
+            return Ok(false);
+        };
+        if from == to {
+            return Ok(true);
+        }
+
+        let value = self
+            .raw_value(keydir_entry)
+            .ok_or_else(|| missing_value(keydir_entry))?
+            .to_vec();
+        let tstamp = self.now();
+        let copy = KeyValueEntry::new(tstamp, to, vec![])
+            .with_stored_value(keydir_entry.flags, value)
+            .with_expiry(keydir_entry.expiry);
+        copy.check_size(&self.options)?;
+        let tombstone = KeyValueEntry::tombstone(tstamp, from);
+        tombstone.check_size(&self.options)?;
+        self.append_batch(vec![copy, tombstone])?;
+
+        Ok(true)
+    }
+
+    /// Rewrites the current value with a fresh timestamp, keeping its expiry.
+    /// Returns `false` if the key is absent.
+    fn touch<K: ToBytes>(&mut self, key: K) -> Result<bool> {
+        let key = key.to_bytes();
+        let Some(keydir_entry) = self.live_entry(&key) else {
+            return Ok(false);
+        };
+
+        let value = self
+            .raw_value(keydir_entry)
+            .ok_or_else(|| missing_value(keydir_entry))?
+            .to_vec();
+        let entry = KeyValueEntry::new(self.now(), key, vec![])
+            .with_stored_value(keydir_entry.flags, value)
+            .with_expiry(keydir_entry.expiry);
+        self.write_stored(entry)?;
+
+        Ok(true)
+    }
+
+    /// Appends `data` to the current value, creating it if the key is absent.
+    fn append<K: ToBytes>(&mut self, key: K, data: &[u8]) -> Result<()> {
+        self.merge_value(key, data, |current, data| {
+            [current.unwrap_or_default(), data].concat()
+        })
+    }
+
+    /// Writes the pair only if the key is absent, which includes deleted and
+    /// expired keys. Returns whether anything was written.
+    fn put_if_absent<K: ToBytes, V: ToBytes>(&mut self, key: K, value: V) -> Result<bool> {
+        let key = key.to_bytes();
+        if self.live_entry(&key).is_some() {
+            return Ok(false);
+        }
+
+        self.put(key, value)?;
+        Ok(true)
+    }
+
+    /// Returns the current value, or stores and returns the result of `f`
+    /// when the key is absent. `f` only runs in the latter case.
+    fn get_or_insert_with<K, V, F>(&mut self, key: K, f: F) -> Result<Vec<u8>>
+    where
+        K: ToBytes,
+        V: ToBytes,
+        F: FnOnce() -> V,
+    {
+        let key = key.to_bytes();
+        if let Some(entry) = self.live_entry(&key) {
+            return self.try_value(entry).map(Cow::into_owned);
+        }
+
+        let value = f().to_bytes();
+        self.put(key, value.clone())?;
+        Ok(value)
+    }
+
+    /// Like `put_batch_atomic`, for any iterator of pairs.
+    fn put_many<K, V, I>(&mut self, pairs: I) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.put_batch_atomic(pairs.into_iter().collect())
+    }
+
+    /// Writes every pair or none of them: all entries are checked and
+    /// encoded before the file, keydir or sequence number is touched, then
+    /// appended as one contiguous write.
+    fn put_batch_atomic<K, V>(&mut self, pairs: Vec<(K, V)>) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        let tstamp = self.now();
+        let entries = pairs
+            .into_iter()
+            .map(|(key, value)| {
+                let entry = KeyValueEntry::new(tstamp, key, value);
+                entry.check_size(&self.options)?;
+                entry.compress(self.options.compression)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.append_batch(entries)
+    }
+
+    /// Appends checked entries, values already in their stored form, as one
+    /// contiguous write and then applies them in order, tombstones removing
+    /// their key. Nothing is touched if any entry fails to encode, and
+    /// eviction runs once at the end.
+    fn append_batch(&mut self, entries: Vec<KeyValueEntry>) -> Result<()> {
+        // Positions are relative to the batch until we know which file it
+        // lands in.
+        let mut batch = vec![];
+        let mut staged = Vec::with_capacity(entries.len());
+        for (entry, seq) in entries.into_iter().zip(self.seq + 1..) {
+            let entry = entry.with_seq(seq);
+            let keydir_entry = (!entry.is_tombstone())
+                .then(|| KeyDirEntry::new(self.file.id, batch.len(), &entry));
+            staged.push((entry.key.clone(), keydir_entry));
+            batch.extend(self.file.encode(entry)?);
+        }
+
+        self.seq += staged.len() as u64;
+        self.rotate_if_needed(batch.len());
+        let (file_id, base) = (self.file.id, self.file.size() as u32);
+        self.file.append(&batch);
+        self.bytes_written += batch.len();
+        for (key, keydir_entry) in staged {
+            let Some(mut keydir_entry) = keydir_entry else {
+                self.keydir.remove(&key);
+                if let Some(lru) = &mut self.lru {
+                    lru.get_mut().expect("lru lock poisoned").remove(&key);
+                }
+                self.options.emit(|| StoreEvent::Delete { key });
+                continue;
+            };
+            keydir_entry.file_id = file_id;
+            keydir_entry.value_pos += base;
+            if let Some(bloom) = &mut self.bloom {
+                bloom.insert(&key);
+            }
+            let value_sz = keydir_entry.value_sz;
+            self.keydir.insert(key.clone(), keydir_entry);
+            self.touch_lru(&key);
+            self.options.emit(|| StoreEvent::Put { key, value_sz });
+        }
+
+        self.evict_if_needed()
+    }
+
+    fn write(&mut self, entry: KeyValueEntry) -> Result<KeyDirEntry> {
+        entry.check_size(&self.options)?;
+        let entry = entry.compress(self.options.compression)?;
+        self.write_stored(entry)
+    }
+
+    /// Appends an entry whose value is already in its stored form, returning
+    /// its keydir entry.
+    fn write_stored(&mut self, entry: KeyValueEntry) -> Result<KeyDirEntry> {
+        let entry = entry.with_seq(self.next_seq());
+        self.rotate_if_needed(entry.size());
+
+        let keydir_entry = KeyDirEntry::new(self.file.id, self.file.size(), &entry);
+        if let Some(bloom) = &mut self.bloom {
+            bloom.insert(&entry.key);
+        }
+        self.keydir.insert(entry.key.clone(), keydir_entry.clone());
+
+        let (key, value_sz) = (entry.key.clone(), entry.value_sz);
+        let entry_data = self.file.encode(entry)?;
+        self.bytes_written += entry_data.len();
+        self.file.append(&entry_data);
+        self.touch_lru(&key);
+        self.options.emit(|| StoreEvent::Put { key, value_sz });
+
+        self.evict_if_needed()?;
+        Ok(keydir_entry)
+    }
+
+    fn delete<K: ToBytes>(&mut self, key: K) -> Result<()> {
+        let entry = KeyValueEntry::tombstone(self.now(), key).with_seq(self.next_seq());
+        entry.check_size(&self.options)?;
+        self.rotate_if_needed(entry.size());
+        self.keydir.remove(&entry.key);
+
+        let key = entry.key.clone();
+        let entry_data = self.file.encode(entry)?;
+        self.file.append(&entry_data);
+        if let Some(lru) = &mut self.lru {
+            lru.get_mut().expect("lru lock poisoned").remove(&key);
+        }
+        self.options.emit(|| StoreEvent::Delete { key });
+
+        Ok(())
+    }
+
+    /// Tombstones every live key starting with `prefix`, returning how many
+    /// were removed. Values are never read.
+    fn delete_prefix(&mut self, prefix: &[u8]) -> Result<usize> {
+        let now = self.now();
+        let keys: Vec<Vec<u8>> = self
+            .keydir
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in &keys {
+            self.delete(key.as_slice())?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Tombstones every live pair `f` rejects, returning how many were
+    /// removed. Each value is read once, decompressed, for the predicate.
+    fn retain<F: FnMut(&[u8], &[u8]) -> bool>(&mut self, mut f: F) -> Result<usize> {
+        let mut rejected = vec![];
+        for (key, entry) in self.live_entries() {
+            if !f(key, &self.try_value(entry)?) {
+                rejected.push(key.to_vec());
+            }
+        }
+
+        for key in &rejected {
+            self.delete(key.as_slice())?;
+        }
+        Ok(rejected.len())
+    }
+
+    fn emit_repair(&self, recovery: RecoveryInfo) {
+        if recovery.bytes_truncated > 0 {
+            let truncated_bytes = recovery.bytes_truncated;
+            self.options
+                .emit(|| StoreEvent::Repaired { truncated_bytes });
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.options.clock.now()
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn stats(&self) -> StoreStats {
+        let (live_keys, live_bytes) = self
+            .live_entries()
+            .fold((0, 0), |(keys, bytes), (key, entry)| {
+                (keys + 1, bytes + entry.encoded_size(key))
+            });
+        let total_bytes = self.total_bytes();
+        let header_bytes = (self.files.len() + 1) * FileHeader::SIZE;
+        let dead_bytes = total_bytes - header_bytes - live_bytes;
+        let fragmentation_ratio = dead_bytes as f64 / total_bytes as f64;
+
+        StoreStats {
+            live_keys,
+            live_bytes,
+            total_bytes,
+            fragmentation_ratio,
+            total_bytes_written: self.bytes_written,
+            total_bytes_reclaimed: self.bytes_reclaimed,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let keydir = self
+            .live_entries()
+            .map(|(key, entry)| (key.to_vec(), entry.clone()))
+            .collect();
+        let mut files = self.files.clone();
+        files.insert(self.file.id, Arc::new(self.file.clone()));
+
+        Snapshot { keydir, files }
+    }
+
+    /// Counts live values by stored size. Each bucket is keyed by its
+    /// inclusive upper bound: 16 for sizes up to 16 bytes, then successive
+    /// powers of two.
+    fn size_histogram(&self) -> BTreeMap<u64, usize> {
+        let mut histogram = BTreeMap::new();
+        for (_, entry) in self.live_entries() {
+            let bound = u64::from(entry.value_sz).next_power_of_two().max(16);
+            *histogram.entry(bound).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Recomputes the checksum of every entry in every file without
+    /// modifying anything. A corrupt entry is skipped using its recorded
+    /// sizes, so scanning carries on; a truncated tail ends the file.
+    fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+
+        for file in self.files_by_id() {
+            let mut cursor = Cursor::new(file.data.as_slice());
+            cursor.set_position(FileHeader::SIZE as u64);
+
+            while (cursor.position() as usize) < file.size() {
+                let offset = cursor.position() as usize;
+                match KeyValueEntry::read_from(&mut cursor, file.byte_order, file.checksum) {
+                    Ok(_) => report.entries_ok += 1,
+                    Err(err) => {
+                        report.entries_corrupt += 1;
+                        report.corrupt_offsets.push((file.id, offset));
+                        if !matches!(err, CaskaError::ChecksumMismatch) {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Drops every key and starts over with a fresh, empty file.
+    fn clear(&mut self) -> Result<()> {
+        self.file = self.file.successor();
+        self.files.clear();
+        self.keydir.clear();
+        self.rebuild_bloom();
+        self.rebuild_lru();
+        Ok(())
+    }
+
+    /// Merges only once `stats().fragmentation_ratio` exceeds
+    /// `merge_threshold`, returning whether it did.
+    fn compact_if_needed(&mut self) -> Result<bool> {
+        if self.stats().fragmentation_ratio <= self.options.merge_threshold {
+            return Ok(false);
+        }
+        self.merge()?;
+        Ok(true)
+    }
+
+    /// Writes just the live entries to a standalone data file at `out_path`,
+    /// leaving this store as it is. Nothing else refers to the copy, so no
+    /// tombstones are carried over.
+    fn compact_to(&self, out_path: impl AsRef<Path>) -> Result<()> {
+        let now = self.now();
+        let mut compacted = self.file.successor();
+        for (key, keydir_entry) in &self.keydir {
+            if !keydir_entry.is_expired(now) {
+                self.copy_entry(key, keydir_entry, &mut compacted)?;
+            }
+        }
+        fs::write(out_path, &compacted.data)?;
+        Ok(())
+    }
+
+    /// Rewrites every live entry into a single new file, dropping
+    /// superseded values, expired keys and tombstones older than
+    /// `tombstone_ttl`.
+    fn merge(&mut self) -> Result<MergeReport> {
+        let prepared = self.prepare_merge()?;
+        Ok(self.install_merge(prepared))
+    }
+
+    /// The read-only half of `merge`: builds the merged file and its keydir
+    /// without touching the store, so readers can carry on meanwhile.
+    fn prepare_merge(&self) -> Result<PreparedMerge> {
+        let started = Instant::now();
+        let files_merged = self.files.len() + 1;
+        let entries_before = self.files_by_id().map(File::entry_count).sum();
+        let now = self.now();
+        let tombstones =
+            retained_tombstones(self.iter_entries(), &self.keydir, &self.options, now)?;
+        let mut merged = self.file.successor();
+        let mut keydir = KeyDir::new();
+
+        for tombstone in tombstones.into_values() {
+            let tombstone_data = merged.encode(tombstone)?;
+            merged.append(&tombstone_data);
+        }
+
+        for (key, keydir_entry) in &self.keydir {
+            if keydir_entry.is_expired(now) {
+                continue;
+            }
+
+            let copied = self.copy_entry(key, keydir_entry, &mut merged)?;
+            keydir.insert(key.clone(), copied);
+        }
+
+        Ok(PreparedMerge {
+            file: merged,
+            keydir,
+            source_id: self.file.id,
+            source_len: self.file.size(),
+            files_merged,
+            entries_before,
+            started,
+        })
+    }
+
+    /// A copy of the store to build a merge from after letting go of the
+    /// original. Immutable files are shared; the keydir and the active file
+    /// are copied.
+    fn merge_source(&self) -> Self {
+        let mut source = Self::with_options(self.options.clone());
+        source.file = self.file.clone();
+        source.files = self.files.clone();
+        source.keydir = self.keydir.clone();
+        source.bloom = None;
+        source.lru = None;
+        source.seq = self.seq;
+        source
+    }
+
+    /// Appends to `prepared` every entry written to the store since it was
+    /// built, so it can be installed without losing them. Returns false if
+    /// the active file has been rotated or cleared in the meantime, which
+    /// leaves the new entries in files replaying can't follow.
+    fn catch_up_merge(&self, prepared: &mut PreparedMerge) -> Result<bool> {
+        if self.file.id != prepared.source_id || self.file.size() < prepared.source_len {
+            return Ok(false);
+        }
+
+        let now = self.now();
+        let mut removed = HashMap::new();
+        let mut cursor = Cursor::new(self.file.data.as_slice());
+        cursor.set_position(prepared.source_len as u64);
+        while (cursor.position() as usize) < self.file.size() {
+            let start = cursor.position() as usize;
+            let entry =
+                KeyValueEntry::read_from(&mut cursor, self.file.byte_order, self.file.checksum)?;
+            let entry_pos = prepared.file.size();
+            prepared
+                .file
+                .append(&self.file.data[start..cursor.position() as usize]);
+            apply_entry(
+                prepared.file.id,
+                entry_pos,
+                entry,
+                now,
+                &mut prepared.keydir,
+                &mut removed,
+            );
+        }
+        prepared.source_len = self.file.size();
+        Ok(true)
+    }
+
+    /// Swaps in a merge from `prepare_merge`. Callers that let go of the
+    /// store in between must run `catch_up_merge` first, or writes made
+    /// since are lost.
+    fn install_merge(&mut self, prepared: PreparedMerge) -> MergeReport {
+        let PreparedMerge {
+            file,
+            keydir,
+            files_merged,
+            entries_before,
+            started,
+            ..
+        } = prepared;
+        let bytes_before = self.total_bytes();
+        let entries_after = file.entry_count();
+        self.file = file;
+        self.files.clear();
+        self.keydir = keydir;
+        self.rebuild_bloom();
+
+        let bytes_after = self.total_bytes();
+        let reclaimed_bytes = bytes_before.saturating_sub(bytes_after);
+        self.bytes_reclaimed += reclaimed_bytes;
+        self.options.emit(|| StoreEvent::Merge { reclaimed_bytes });
+
+        MergeReport {
+            files_merged,
+            entries_before,
+            entries_after,
+            bytes_before,
+            bytes_after,
+            reclaimed_bytes,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    /// Compacts just the immutable files in `file_ids` into one file, which
+    /// takes the highest of their ids. Other files and the keydir entries
+    /// pointing into them are left alone. Tombstones for keys that aren't
+    /// live are carried over, since files outside the merge may still hold
+    /// the values they delete.
+    fn merge_files(&mut self, file_ids: &[u32]) -> Result<MergeReport> {
+        // Oldest first, so the newest tombstone for a key is the one kept.
+        let mut file_ids = file_ids.to_vec();
+        file_ids.sort_unstable();
+        file_ids.dedup();
+        let Some(&merged_id) = file_ids.last() else {
+            return Ok(MergeReport::default());
+        };
+        if file_ids.iter().any(|id| !self.files.contains_key(id)) {
+            return Err(CaskaError::Unsupported(
+                "only immutable files can be merged",
+            ));
+        }
+
+        let started = Instant::now();
+        let now = self.now();
+        let mut merged = File::new(merged_id, self.file.byte_order, self.file.checksum);
+        let (entries_before, bytes_before) = file_ids
+            .iter()
+            .map(|file_id| &self.files[file_id])
+            .fold((0, 0), |(entries, bytes), file| {
+                (entries + file.entry_count(), bytes + file.size())
+            });
+
+        let mut tombstones = BTreeMap::new();
+        for &file_id in &file_ids {
+            for entry in self.files[&file_id].entries() {
+                let entry = entry?;
+                if entry.is_tombstone() && !self.keydir.contains_key(&entry.key) {
+                    tombstones.insert(entry.key.clone(), entry);
+                }
+            }
+        }
+        for tombstone in tombstones.into_values() {
+            let tombstone_data = merged.encode(tombstone)?;
+            merged.append(&tombstone_data);
+        }
+
+        let mut expired = vec![];
+        let mut copied = vec![];
+        for (key, keydir_entry) in &self.keydir {
+            if !file_ids.contains(&keydir_entry.file_id) {
+                continue;
+            }
+            if keydir_entry.is_expired(now) {
+                expired.push(key.clone());
+                continue;
+            }
+            copied.push((
+                key.clone(),
+                self.copy_entry(key, keydir_entry, &mut merged)?,
+            ));
+        }
+
+        let entries_after = merged.entry_count();
+        let bytes_after = merged.size();
+        for file_id in &file_ids {
+            self.files.remove(file_id);
+        }
+        self.files.insert(merged_id, Arc::new(merged));
+        for key in expired {
+            self.keydir.remove(&key);
+        }
+        self.keydir.extend(copied);
+        self.rebuild_bloom();
+        self.rebuild_lru();
+
+        let reclaimed_bytes = bytes_before.saturating_sub(bytes_after);
+        self.bytes_reclaimed += reclaimed_bytes;
+        self.options.emit(|| StoreEvent::Merge { reclaimed_bytes });
+
+        Ok(MergeReport {
+            files_merged: file_ids.len(),
+            entries_before,
+            entries_after,
+            bytes_before,
+            bytes_after,
+            reclaimed_bytes,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Appends `key`'s live entry to `file` unchanged, returning where it
+    /// now lives.
+    fn copy_entry(
+        &self,
+        key: &[u8],
+        keydir_entry: &KeyDirEntry,
+        file: &mut File,
+    ) -> Result<KeyDirEntry> {
+        let value = self
+            .raw_value(keydir_entry)
+            .ok_or_else(|| missing_value(keydir_entry))?;
+        let entry = KeyValueEntry::restore(key.to_vec(), keydir_entry, value.to_vec());
+
+        let copied = KeyDirEntry::new(file.id, file.size(), &entry);
+        let entry_data = file.encode(entry)?;
+        file.append(&entry_data);
+        Ok(copied)
+    }
+
+    /// Share of each immutable file's bytes that no live key points at.
+    fn file_fragmentation(&self) -> BTreeMap<u32, f64> {
+        let mut live_bytes: HashMap<u32, usize> = HashMap::new();
+        for (key, entry) in self.live_entries() {
+            *live_bytes.entry(entry.file_id).or_insert(0) += entry.encoded_size(key);
+        }
+
+        self.files
+            .values()
+            .map(|file| {
+                let live = live_bytes.get(&file.id).copied().unwrap_or(0);
+                let dead = file.size() - FileHeader::SIZE - live;
+                (file.id, dead as f64 / file.size() as f64)
+            })
+            .collect()
+    }
+
+    fn touch_lru(&mut self, key: &[u8]) {
+        if let Some(lru) = &mut self.lru {
+            lru.get_mut().expect("lru lock poisoned").touch(key);
+        }
+    }
+
+    /// Deletes least recently used keys until at most `max_entries` remain.
+    fn evict_if_needed(&mut self) -> Result<()> {
+        let Some(max_entries) = self.options.max_entries else {
+            return Ok(());
+        };
+        // The keydir also holds expired keys, so it's never smaller than the
+        // live count; walking it is only worth it once it's over the cap.
+        if self.keydir.len() <= max_entries {
+            return Ok(());
+        }
+
+        let mut live = self.len();
+        while live > max_entries {
+            let Some(lru) = &mut self.lru else {
+                break;
+            };
+            let Some(key) = lru.get_mut().expect("lru lock poisoned").pop_oldest() else {
+                break;
+            };
+            // Keys dropped by a merge or already expired can linger in the
+            // lru; skip them.
+            if self.live_entry(&key).is_some() {
+                self.delete(key)?;
+                live -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Seeds the access order from write timestamps, oldest first.
+    fn rebuild_lru(&mut self) {
+        let Some(lru) = &mut self.lru else {
+            return;
+        };
+        let lru = lru.get_mut().expect("lru lock poisoned");
+        lru.clear();
+
+        let mut keys: Vec<_> = self.keydir.iter().collect();
+        keys.sort_by_key(|(_, entry)| entry.tstamp);
+        for (key, _) in keys {
+            lru.touch(key);
+        }
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.files.values().map(|file| file.size()).sum::<usize>() + self.file.size()
+    }
+
+    fn rebuild_bloom(&mut self) {
+        if let Some(bloom) = &mut self.bloom {
+            bloom.clear();
+            for key in self.keydir.keys() {
+                bloom.insert(key);
+            }
+        }
+    }
+
+    /// Moves the active file into the immutable set when `entry_size` more
+    /// bytes would push it past `max_file_size`.
+    fn rotate_if_needed(&mut self, entry_size: usize) {
+        if self.file.is_empty() || self.file.size() + entry_size <= self.options.max_file_size {
+            return;
+        }
+
+        let next = self.file.successor();
+        let file = mem::replace(&mut self.file, next);
+        self.files.insert(file.id, Arc::new(file));
+    }
+}
+
+fn missing_value(entry: &KeyDirEntry) -> CaskaError {
+    CaskaError::InvalidEntry(format!("value missing from file {}", entry.file_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn encode_primitive_types_as_big_endian_bytes() {
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 1, 2], 258u64.to_bytes());
+        assert_eq!(vec![255, 255, 255, 254], (-2i32).to_bytes());
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 1, 2], 258i64.to_bytes());
+        assert_eq!(vec![255; 8], (-1i64).to_bytes());
+        assert_eq!(vec![1], true.to_bytes());
+        assert_eq!(vec![0], false.to_bytes());
+        assert_eq!(vec![1, 2, 3], [1u8, 2, 3].as_slice().to_bytes());
+        assert_eq!(vec![0, 255], b"\x00\xff".to_bytes());
+        assert_eq!(b"hello".to_vec(), String::from("hello").to_bytes());
+    }
+
+    #[test]
+    fn encode_a_key_value_entry() {
+        let entry = KeyValueEntry::new(42, 42, 42);
+        let got: Vec<u8> = entry.try_into().unwrap();
+        let want = vec![
+            94, 25, 91, 152, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 42, 0, 0, 0, 42,
+        ];
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn decode_a_key_value_entry() {
+        let bytes = vec![
+            94, 25, 91, 152, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 42, 0, 0, 0, 42,
+        ];
+        let want = KeyValueEntry::new(42, 42, 42);
+        let got = bytes.try_into().unwrap();
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn memory_store_can_set_and_get_a_key_value_pair() {
+        let mut store = MemoryStore::new();
+
+        let key_value_pairs = vec![
+            ("hello", "world"),
+            ("first_name", "john"),
+            ("last_name", "smith"),
+        ];
+        for (key, value) in key_value_pairs {
+            store.put(key, value).unwrap();
+
+            let want = value;
+            let got = store.get(key).unwrap();
 
             assert_eq!(want, String::from_utf8_lossy(&got));
         }
     }
+
+    #[test]
+    fn decode_a_corrupted_key_value_entry() {
+        let mut bytes = vec![
+            94, 25, 91, 152, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 42, 0, 0, 0, 42,
+        ];
+        bytes[44] ^= 0xff;
+        let err = KeyValueEntry::try_from(bytes).unwrap_err();
+        assert!(matches!(err, CaskaError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn decode_a_truncated_key_value_entry() {
+        let entry = KeyValueEntry::new(42, "hello", "world");
+        let mut bytes: Vec<u8> = entry.try_into().unwrap();
+        bytes.pop();
+
+        let err = KeyValueEntry::try_from(bytes).unwrap_err();
+        assert!(matches!(err, CaskaError::Truncated));
+    }
+
+    #[test]
+    fn decode_rejects_lengths_longer_than_the_data() {
+        let entry = KeyValueEntry::new(42, "hello", "world");
+        let mut bytes: Vec<u8> = entry.try_into().unwrap();
+        // A flipped bit in `value_sz` claiming a ~4 GiB value.
+        let value_sz = KeyValueEntry::HEADER_SIZE - size_of::<u32>();
+        bytes[value_sz..KeyValueEntry::HEADER_SIZE].copy_from_slice(&(TOMBSTONE - 1).to_be_bytes());
+
+        let err = KeyValueEntry::try_from(bytes).unwrap_err();
+        assert!(matches!(err, CaskaError::Truncated));
+    }
+
+    #[test]
+    fn encode_and_decode_a_tombstone() {
+        let entry = KeyValueEntry::tombstone(42, "hello");
+        let bytes: Vec<u8> = entry.try_into().unwrap();
+        let got: KeyValueEntry = bytes.try_into().unwrap();
+        assert!(got.is_tombstone());
+        assert_eq!(b"hello".to_vec(), got.key);
+        assert!(got.value.is_empty());
+    }
+
+    #[test]
+    fn memory_store_can_delete_a_key() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        let size_before = store.file.size();
+
+        store.delete("hello").unwrap();
+
+        assert_eq!(None, store.get("hello"));
+        assert!(store.file.size() > size_before);
+    }
+
+    #[test]
+    fn memory_store_can_put_a_key_after_deleting_it() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.delete("hello").unwrap();
+
+        store.put("hello", "again").unwrap();
+
+        let got = store.get("hello").unwrap();
+        assert_eq!("again", String::from_utf8_lossy(&got));
+    }
+
+    #[test]
+    fn memory_store_contains_key() {
+        let mut store = MemoryStore::new();
+        assert!(!store.contains_key("hello"));
+
+        store.put("hello", "world").unwrap();
+        assert!(store.contains_key("hello"));
+
+        store.delete("hello").unwrap();
+        assert!(!store.contains_key("hello"));
+    }
+
+    #[test]
+    fn memory_store_iterates_over_live_keys() {
+        let mut store = MemoryStore::new();
+        store.put("a", "1").unwrap();
+        store.put("b", "2").unwrap();
+        store.put("c", "3").unwrap();
+        store.put("a", "4").unwrap();
+        store.delete("b").unwrap();
+
+        let got: Vec<&[u8]> = store.keys().collect();
+
+        assert_eq!(vec![b"a".as_slice(), b"c".as_slice()], got);
+    }
+
+    #[test]
+    fn memory_store_len_counts_live_keys() {
+        let mut store = MemoryStore::new();
+        assert!(store.is_empty());
+
+        for i in 0..10 {
+            store.put("hello", i).unwrap();
+        }
+
+        assert_eq!(1, store.len());
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn memory_store_can_be_rebuilt_from_bytes() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "again").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("last_name").unwrap();
+
+        let replayed = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+
+        assert_eq!(store.len(), replayed.len());
+        assert_eq!(b"again".to_vec(), replayed.get("hello").unwrap());
+        assert_eq!(b"john".to_vec(), replayed.get("first_name").unwrap());
+        assert_eq!(None, replayed.get("last_name"));
+    }
+
+    #[test]
+    fn memory_store_rotates_to_a_new_file_past_max_file_size() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(32));
+
+        store.put("hello", "world").unwrap();
+        let first_file_id = store.file.id;
+        store.put("first_name", "john").unwrap();
+        store.put("last_name", "smith").unwrap();
+
+        assert!(!store.files.is_empty());
+        assert_ne!(first_file_id, store.file.id);
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+        assert_eq!(b"smith".to_vec(), store.get("last_name").unwrap());
+    }
+
+    #[test]
+    fn put_returning_offset_locates_the_stored_value() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        for i in 0..10u32 {
+            let value = format!("value-{i}");
+            let entry = store.put_returning_offset(i, value.as_str()).unwrap();
+
+            let data = &store.file(entry.file_id).unwrap().data;
+            let start = entry.value_pos as usize;
+            let stored = &data[start..start + entry.value_sz as usize];
+            assert_eq!(value.as_bytes(), stored);
+        }
+        assert!(!store.files.is_empty());
+    }
+
+    #[test]
+    fn value_pos_points_at_the_encoded_value_for_random_pairs() {
+        // xorshift64, so failures reproduce without a rand dependency.
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for byte_order in [Endianness::Big, Endianness::Little] {
+            for checksum in [ChecksumKind::Crc32, ChecksumKind::XxHash64] {
+                let options = Options::new()
+                    .byte_order(byte_order)
+                    .checksum(checksum)
+                    .max_file_size(4096);
+                let mut store = MemoryStore::with_options(options);
+                for _ in 0..200 {
+                    let key: Vec<u8> = (0..1 + next() % 32).map(|_| next() as u8).collect();
+                    let value: Vec<u8> = (0..next() % 256).map(|_| next() as u8).collect();
+                    store.put(key.as_slice(), value.as_slice()).unwrap();
+
+                    let entry = &store.keydir[&key];
+                    let data = &store.file(entry.file_id).unwrap().data;
+                    let start = entry.value_pos as usize;
+                    let end = start + entry.value_sz as usize;
+                    assert_eq!(value.len(), entry.value_sz as usize);
+                    assert_eq!(value.as_slice(), &data[start..end], "key {key:?}");
+                    if entry.file_id == store.file.id {
+                        assert_eq!(data.len(), end, "value must end the entry");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overwriting_with_a_shorter_value_leaves_no_trailing_bytes() {
+        let mut store = MemoryStore::new();
+        store.put("hello", vec![0xAB; 100]).unwrap();
+        store.put("hello", vec![0x01]).unwrap();
+
+        assert_eq!(1, store.keydir[b"hello".as_slice()].value_sz);
+        assert_eq!(vec![0x01], store.get("hello").unwrap());
+
+        let reloaded = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+        assert_eq!(vec![0x01], reloaded.get("hello").unwrap());
+    }
+
+    #[test]
+    fn file_count_and_active_file_id_follow_rotation() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(32));
+        store.put("hello", "world").unwrap();
+        assert_eq!(1, store.file_count());
+        let first_id = store.active_file_id();
+
+        store.put("first_name", "john").unwrap();
+        assert_eq!(2, store.file_count());
+        assert!(store.active_file_id() > first_id);
+        assert!(store.files.keys().all(|&id| id < store.active_file_id()));
+
+        store.merge().unwrap();
+        assert_eq!(1, store.file_count());
+    }
+
+    #[test]
+    fn get_cow_borrows_unless_the_value_was_compressed() {
+        for compression in [Compression::None, Compression::Zstd] {
+            let mut store = MemoryStore::with_options(Options::new().compression(compression));
+            let value = "world".repeat(100);
+            store.put("hello", value.as_str()).unwrap();
+
+            let got = store.get_cow("hello").unwrap();
+            assert_eq!(value.as_bytes(), &*got);
+            assert_eq!(
+                compression == Compression::None,
+                matches!(got, Cow::Borrowed(_))
+            );
+            assert_eq!(None, store.get_cow("missing"));
+        }
+    }
+
+    #[test]
+    fn memory_store_get_reads_from_the_file_an_entry_points_at() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(32));
+        store.put("key1", "old").unwrap();
+        store.put("key2", "new").unwrap();
+
+        // Same offset in both files, so only `file_id` tells them apart.
+        let first = &store.keydir[b"key1".as_slice()];
+        let second = &store.keydir[b"key2".as_slice()];
+        assert_ne!(first.file_id, second.file_id);
+        assert_eq!(first.value_pos, second.value_pos);
+        assert_eq!(b"old".to_vec(), store.get("key1").unwrap());
+        assert_eq!(b"new".to_vec(), store.get("key2").unwrap());
+    }
+
+    #[test]
+    fn memory_store_merge_discards_stale_entries() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        for i in 0..100 {
+            store.put("hello", i).unwrap();
+        }
+        store.put("first_name", "john").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("last_name").unwrap();
+        let size_before: usize =
+            store.files.values().map(|file| file.size()).sum::<usize>() + store.file.size();
+
+        store.merge().unwrap();
+
+        let live_size = KeyValueEntry::new(0, "hello", 99).size()
+            + KeyValueEntry::new(0, "first_name", "john").size();
+        assert!(store.files.is_empty());
+        assert_eq!(FileHeader::SIZE + live_size, store.file.size());
+        assert!(store.file.size() * 10 < size_before);
+        assert_eq!(99u32.to_bytes(), store.get("hello").unwrap());
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+        assert_eq!(None, store.get("last_name"));
+    }
+
+    #[test]
+    fn memory_store_merge_reports_what_it_reclaimed() {
+        let mut store = MemoryStore::new();
+        for i in 0..10u32 {
+            store.put("hello", i).unwrap();
+        }
+        let entry_size = KeyValueEntry::new(0, "hello", 0u32).size();
+
+        let report = store.merge().unwrap();
+
+        assert_eq!(1, report.files_merged);
+        assert_eq!(10, report.entries_before);
+        assert_eq!(1, report.entries_after);
+        assert_eq!(FileHeader::SIZE + 10 * entry_size, report.bytes_before);
+        assert_eq!(FileHeader::SIZE + entry_size, report.bytes_after);
+        assert_eq!(store.total_bytes(), report.bytes_after);
+        assert_eq!(9 * entry_size, report.reclaimed_bytes);
+    }
+
+    #[test]
+    fn memory_store_merges_only_the_selected_files() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(200));
+        for i in 0..12u32 {
+            store.put(i, i).unwrap();
+        }
+        let oldest = *store.files.keys().min().unwrap();
+        let stale: Vec<u32> = store
+            .keydir
+            .iter()
+            .filter(|(_, entry)| entry.file_id == oldest)
+            .map(|(key, _)| BigEndian::read_u32(key))
+            .collect();
+        for &i in &stale {
+            store.put(i, i + 100).unwrap();
+        }
+        store.delete(11u32).unwrap();
+
+        let candidates = MergePolicy::new(0.5).candidates(&store);
+        assert_eq!(vec![oldest], candidates);
+
+        let untouched: Vec<(u32, usize)> = store
+            .files
+            .values()
+            .filter(|file| file.id != oldest)
+            .map(|file| (file.id, file.size()))
+            .collect();
+        let size_before = store.total_bytes();
+
+        let report = store.merge_files(&candidates).unwrap();
+
+        assert_eq!(1, report.files_merged);
+        assert_eq!(stale.len(), report.entries_before);
+        assert_eq!(0, report.entries_after);
+        assert_eq!(FileHeader::SIZE, report.bytes_after);
+        assert_eq!(size_before - store.total_bytes(), report.reclaimed_bytes);
+        assert_eq!(FileHeader::SIZE, store.files[&oldest].size());
+        for (file_id, size) in untouched {
+            assert_eq!(size, store.files[&file_id].size());
+        }
+        for i in 0..11u32 {
+            let want = if stale.contains(&i) { i + 100 } else { i };
+            assert_eq!(want.to_bytes(), store.get(i).unwrap());
+        }
+        assert_eq!(None, store.get(11u32));
+    }
+
+    #[test]
+    fn memory_store_merge_files_rejects_the_active_file() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+
+        let err = store.merge_files(&[store.file.id]).unwrap_err();
+        assert!(matches!(err, CaskaError::Unsupported(_)));
+    }
+
+    #[test]
+    fn memory_store_compresses_values() {
+        let value = "a".repeat(1024);
+        for compression in [Compression::Lz4, Compression::Zstd] {
+            let mut store = MemoryStore::with_options(Options::new().compression(compression));
+            store.put("hello", value.as_str()).unwrap();
+
+            let entry = store.live_entry(b"hello").unwrap();
+            assert!((entry.value_sz as usize) < value.len());
+            assert!(store.file.size() < value.len());
+            assert_eq!(value.as_bytes(), store.get("hello").unwrap());
+        }
+    }
+
+    #[test]
+    fn memory_store_keeps_values_compressed_across_merge_and_reload() {
+        let value = "a".repeat(1024);
+        let options = Options::new().compression(Compression::Lz4);
+        let mut store = MemoryStore::with_options(options.clone());
+        store.put("hello", value.as_str()).unwrap();
+        store.put_many([("first_name", "john")]).unwrap();
+
+        store.merge().unwrap();
+        assert!(store.file.size() < value.len());
+
+        let store = MemoryStore::from_bytes_with(store.file.data.clone(), options).unwrap();
+        assert_eq!(value.as_bytes(), store.get("hello").unwrap());
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn memory_store_verify_pinpoints_a_corrupt_entry() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        let corrupt_offset = store.file.size();
+        store.put("first_name", "john").unwrap();
+        store.put("last_name", "smith").unwrap();
+        let value_offset = store.live_entry(b"first_name").unwrap().value_pos as usize;
+        store.file.data[value_offset] ^= 0xff;
+
+        let report = store.verify().unwrap();
+
+        assert_eq!(
+            VerifyReport {
+                entries_ok: 2,
+                entries_corrupt: 1,
+                corrupt_offsets: vec![(store.file.id, corrupt_offset)],
+            },
+            report
+        );
+    }
+
+    #[test]
+    fn memory_store_round_trips_binary_keys() {
+        let keys: [&[u8]; 3] = [b"\x00", b"hello\x00world", b"\xff\xfe\xfd"];
+        let mut store = MemoryStore::new();
+        for (i, key) in keys.iter().enumerate() {
+            store.put(*key, i as u32).unwrap();
+        }
+        store.delete(b"\xff\xfe\xfd").unwrap();
+
+        let store = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+
+        assert_eq!(Some(0u32.to_bytes()), store.get(b"\x00"));
+        assert_eq!(Some(1u32.to_bytes()), store.get(b"hello\x00world"));
+        assert_eq!(None, store.get(b"hello"));
+        assert_eq!(None, store.get(b"\xff\xfe\xfd"));
+    }
+
+    #[test]
+    fn memory_store_keeps_empty_values_apart_from_missing_keys() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "").unwrap();
+
+        let store = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+        assert_eq!(Some(vec![]), store.get("hello"));
+        assert_eq!(None, store.get("missing"));
+    }
+
+    #[test]
+    fn memory_store_rejects_empty_keys() {
+        let mut store = MemoryStore::new();
+
+        assert!(matches!(store.put("", "world"), Err(CaskaError::EmptyKey)));
+        assert!(matches!(
+            store.put_many([("hello", "world"), ("", "there")]),
+            Err(CaskaError::EmptyKey)
+        ));
+        assert!(matches!(store.delete(""), Err(CaskaError::EmptyKey)));
+        assert!(store.keydir.is_empty());
+    }
+
+    #[test]
+    fn memory_store_scan_page_visits_every_key_once() {
+        let mut store = MemoryStore::new();
+        for key in ["e", "b", "d", "a", "c"] {
+            store.put(key, key).unwrap();
+        }
+
+        let mut keys = vec![];
+        let mut pages = 0;
+        let mut cursor = None;
+        loop {
+            let (page, next) = store.scan_page(cursor.as_deref(), 2).unwrap();
+            assert!(page.len() <= 2);
+            pages += 1;
+            keys.extend(page.into_iter().map(|(key, _)| key));
+            cursor = next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(3, pages);
+        let want: Vec<_> = ["a", "b", "c", "d", "e"].map(ToBytes::to_bytes).into();
+        assert_eq!(want, keys);
+    }
+
+    #[test]
+    fn memory_store_scan_page_ends_without_an_empty_page() {
+        let mut store = MemoryStore::new();
+        store.put("a", "a").unwrap();
+        store.put("b", "b").unwrap();
+
+        let (page, cursor) = store.scan_page(None, 2).unwrap();
+        assert_eq!(2, page.len());
+        assert_eq!(None, cursor);
+    }
+
+    #[test]
+    fn memory_store_scan_page_refuses_a_zero_limit() {
+        let mut store = MemoryStore::new();
+        store.put("a", "a").unwrap();
+
+        let err = store.scan_page(None, 0).unwrap_err();
+        assert!(matches!(err, CaskaError::Unsupported(_)));
+    }
+
+    #[test]
+    fn dump_index_points_at_each_live_value() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        for i in 0..10 {
+            store
+                .put(format!("key-{}", i % 4), format!("value-{i}"))
+                .unwrap();
+        }
+        store.delete("key-0").unwrap();
+
+        let index = store.dump_index();
+        assert_eq!(3, index.len());
+        for (key, file_id, value_pos, value_sz) in index {
+            let data = &store.file(file_id).unwrap().data;
+            let value = &data[value_pos as usize..(value_pos + value_sz) as usize];
+            assert_eq!(store.get(key).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn value_size_matches_the_value_get_returns() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "").unwrap();
+        store.put("blob", vec![7; 300]).unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("last_name").unwrap();
+
+        for key in ["hello", "first_name", "blob"] {
+            let len = store.get(key).unwrap().len();
+            assert_eq!(Some(len as u32), store.value_size(key));
+        }
+        assert_eq!(None, store.value_size("last_name"));
+        assert_eq!(None, store.value_size("missing"));
+    }
+
+    #[test]
+    fn scan_numeric_range_returns_keys_in_numeric_order() {
+        let mut store = MemoryStore::new();
+        for i in (1..100u64).rev() {
+            store.put(i, format!("value-{i}")).unwrap();
+        }
+        // Sorts inside [10, 20) by bytes, but isn't a u64.
+        store
+            .put([0, 0, 0, 0, 0, 0, 0, 15, 0].as_slice(), "bytes")
+            .unwrap();
+
+        let got: Vec<_> = store.scan_numeric_range(10, 20).collect();
+        let want: Vec<_> = (10..20u64)
+            .map(|i| (i, format!("value-{i}").into_bytes()))
+            .collect();
+        assert_eq!(want, got);
+        assert_eq!(0, store.scan_numeric_range(20, 10).count());
+    }
+
+    #[test]
+    fn keys_with_prefix_returns_only_matching_keys_sorted() {
+        let mut store = MemoryStore::new();
+        for key in ["user:2", "users", "user:10", "use", "user:1", "session:1"] {
+            store.put(key, "value").unwrap();
+        }
+        store.delete("user:10").unwrap();
+
+        let want: Vec<Vec<u8>> = vec![b"user:1".to_vec(), b"user:2".to_vec()];
+        assert_eq!(want, store.keys_with_prefix(b"user:"));
+        assert_eq!(5, store.keys_with_prefix(b"").len());
+        assert_eq!(
+            vec![
+                b"use".to_vec(),
+                b"user:1".to_vec(),
+                b"user:2".to_vec(),
+                b"users".to_vec()
+            ],
+            store.keys_with_prefix(b"use")
+        );
+        assert!(store.keys_with_prefix(b"users:").is_empty());
+    }
+
+    #[test]
+    fn modify_rewrites_present_values_in_place() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+
+        assert!(store.modify("hello", |value| value.push(b'!')).unwrap());
+        assert_eq!(b"world!".to_vec(), store.get("hello").unwrap());
+
+        assert!(store.modify("hello", |value| value.truncate(2)).unwrap());
+        assert_eq!(b"wo".to_vec(), store.get("hello").unwrap());
+
+        let mut called = false;
+        assert!(!store.modify("missing", |_| called = true).unwrap());
+        assert!(!called);
+        assert_eq!(None, store.get("missing"));
+    }
+
+    #[test]
+    fn rename_key_moves_values_and_overwrites_the_target() {
+        let mut store = MemoryStore::with_options(Options::new().compression(Compression::Lz4));
+        store.put("hello", "world".repeat(20).as_str()).unwrap();
+        store.put("first_name", "john").unwrap();
+
+        assert!(store.rename_key("hello", "greeting").unwrap());
+        assert_eq!(None, store.get("hello"));
+        assert_eq!(
+            "world".repeat(20).into_bytes(),
+            store.get("greeting").unwrap()
+        );
+
+        assert!(store.rename_key("greeting", "first_name").unwrap());
+        assert_eq!(None, store.get("greeting"));
+        assert_eq!(
+            "world".repeat(20).into_bytes(),
+            store.get("first_name").unwrap()
+        );
+        assert_eq!(1, store.len());
+
+        assert!(!store.rename_key("missing", "first_name").unwrap());
+        assert!(store.rename_key("first_name", "first_name").unwrap());
+        assert_eq!(
+            "world".repeat(20).into_bytes(),
+            store.get("first_name").unwrap()
+        );
+
+        let reloaded = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+        assert_eq!(None, reloaded.get("hello"));
+        assert_eq!(None, reloaded.get("greeting"));
+        assert_eq!(1, reloaded.len());
+    }
+
+    #[test]
+    fn rename_key_does_not_evict_while_the_source_is_still_live() {
+        let mut store = MemoryStore::with_options(Options::new().max_entries(2));
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.get("hello");
+        let seq = store.seq;
+
+        assert!(store.rename_key("hello", "greeting").unwrap());
+
+        assert_eq!(seq + 2, store.seq);
+        assert_eq!(2, store.len());
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+        assert_eq!(b"world".to_vec(), store.get("greeting").unwrap());
+        assert_eq!(None, store.get("hello"));
+    }
+
+    #[test]
+    fn memory_store_delete_prefix_removes_only_matching_keys() {
+        let mut store = MemoryStore::new();
+        for key in ["user:1", "user:2", "user:3", "users", "session:1"] {
+            store.put(key, "value").unwrap();
+        }
+
+        assert_eq!(3, store.delete_prefix(b"user:").unwrap());
+
+        let keys: Vec<_> = store.keys().collect();
+        assert_eq!(vec![b"session:1".as_slice(), b"users".as_slice()], keys);
+        assert_eq!(0, store.delete_prefix(b"user:").unwrap());
+    }
+
+    #[test]
+    fn memory_store_retain_removes_rejected_pairs() {
+        let mut store = MemoryStore::new();
+        for key in ["user:1", "user:2", "users", "session:1"] {
+            store.put(key, key.len() as u32).unwrap();
+        }
+
+        let mut seen = vec![];
+        let removed = store
+            .retain(|key, value| {
+                seen.push((key.to_vec(), value.to_vec()));
+                key.starts_with(b"user:")
+            })
+            .unwrap();
+
+        assert_eq!(2, removed);
+        assert_eq!(4, seen.len());
+        assert!(seen.contains(&(b"users".to_vec(), 5u32.to_bytes())));
+        let keys: Vec<_> = store.keys().collect();
+        assert_eq!(vec![b"user:1".as_slice(), b"user:2".as_slice()], keys);
+        assert_eq!(None, store.get("session:1"));
+    }
+
+    #[test]
+    fn memory_store_reports_mutations_to_the_event_callback() {
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = events.clone();
+        let options = Options::new().on_event(Box::new(move |event| {
+            recorded.lock().unwrap().push(event.clone());
+        }));
+        let mut store = MemoryStore::with_options(options);
+
+        store.put("hello", "world").unwrap();
+        store.put_many([("first_name", "john")]).unwrap();
+        store.delete("hello").unwrap();
+        store.merge().unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(
+            StoreEvent::Put {
+                key: b"hello".to_vec(),
+                value_sz: 5,
+            },
+            events[0]
+        );
+        assert_eq!(
+            StoreEvent::Put {
+                key: b"first_name".to_vec(),
+                value_sz: 4,
+            },
+            events[1]
+        );
+        assert_eq!(
+            StoreEvent::Delete {
+                key: b"hello".to_vec(),
+            },
+            events[2]
+        );
+        assert!(matches!(
+            events[3],
+            StoreEvent::Merge { reclaimed_bytes } if reclaimed_bytes > 0
+        ));
+        assert_eq!(4, events.len());
+    }
+
+    #[test]
+    fn memory_store_evicts_least_recently_used_keys() {
+        let mut store = MemoryStore::with_options(Options::new().max_entries(3));
+        store.put("a", "1").unwrap();
+        store.put("b", "2").unwrap();
+        store.put("c", "3").unwrap();
+        assert_eq!(Some(b"1".to_vec()), store.get("a"));
+
+        store.put("d", "4").unwrap();
+        assert_eq!(3, store.len());
+        assert_eq!(None, store.get("b"));
+
+        store.put("c", "5").unwrap();
+        store.put("e", "6").unwrap();
+        assert_eq!(None, store.get("a"));
+        assert_eq!(Some(b"5".to_vec()), store.get("c"));
+        assert_eq!(Some(b"4".to_vec()), store.get("d"));
+        assert_eq!(Some(b"6".to_vec()), store.get("e"));
+    }
+
+    #[test]
+    fn memory_store_stays_at_max_entries_over_many_writes() {
+        let mut store = MemoryStore::with_options(Options::new().max_entries(100));
+        for i in 0..10_000u32 {
+            store.put(i, i).unwrap();
+        }
+
+        assert_eq!(100, store.len());
+        assert_eq!(100, store.keydir.len());
+        assert_eq!(None, store.get(9_899u32));
+        assert_eq!(Some(9_900u32.to_be_bytes().to_vec()), store.get(9_900u32));
+    }
+
+    #[test]
+    fn expired_keys_do_not_count_towards_max_entries() {
+        let mut store = MemoryStore::with_options(Options::new().max_entries(2));
+        store.put("a", "1").unwrap();
+        store.put_with_ttl("b", "2", Duration::ZERO).unwrap();
+        store.put("c", "3").unwrap();
+
+        assert_eq!(2, store.len());
+        assert_eq!(Some(b"1".to_vec()), store.get("a"));
+        assert_eq!(Some(b"3".to_vec()), store.get("c"));
+    }
+
+    #[test]
+    fn memory_store_counts_matching_keys() {
+        let mut store = MemoryStore::new();
+        for key in ["user:1", "user:2", "user:3", "session:1"] {
+            store.put(key, "value").unwrap();
+        }
+        store.delete("user:2").unwrap();
+
+        let want = store.scan_prefix(b"user:").count();
+        assert_eq!(2, want);
+        assert_eq!(want, store.count_matching(|key| key.starts_with(b"user:")));
+        assert_eq!(3, store.count_matching(|_| true));
+    }
+
+    #[test]
+    fn memory_store_snapshot_shares_immutable_files() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        for i in 0..10u32 {
+            store.put(i, "value").unwrap();
+        }
+        assert!(!store.files.is_empty());
+
+        let snapshot = store.snapshot();
+        for (id, file) in &store.files {
+            assert!(Arc::ptr_eq(file, &snapshot.files[id]));
+        }
+        assert_eq!(store.file.data, snapshot.files[&store.file.id].data);
+    }
+
+    #[test]
+    fn memory_store_snapshot_ignores_later_writes() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "world").unwrap();
+
+        let snapshot = store.snapshot();
+        store.put("hello", "there").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("first_name").unwrap();
+        store.merge().unwrap();
+
+        let got: Vec<_> = snapshot.iter().collect();
+        assert_eq!(
+            vec![
+                (b"first_name".to_vec(), b"john".to_vec()),
+                (b"hello".to_vec(), b"world".to_vec()),
+            ],
+            got
+        );
+    }
+
+    #[test]
+    fn memory_store_export_round_trips_live_pairs() {
+        let mut store = MemoryStore::with_options(Options::new().compression(Compression::Lz4));
+        store.put("hello", "world").unwrap();
+        store.put("hello", "there").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("last_name").unwrap();
+
+        let mut buf = vec![];
+        store.export(&mut buf).unwrap();
+        let imported = MemoryStore::import(&mut buf.as_slice()).unwrap();
+
+        assert_eq!(
+            store.scan_range(..).collect::<Vec<_>>(),
+            imported.scan_range(..).collect::<Vec<_>>()
+        );
+        assert_eq!(2, imported.iter_entries().count());
+    }
+
+    #[test]
+    fn memory_store_import_checks_lengths_before_reading() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        let mut buf = vec![];
+        store.export(&mut buf).unwrap();
+
+        // Claims a ~4 GiB value that the stream doesn't hold.
+        let value_sz = EXPORT_MAGIC.len() + size_of::<u64>() + size_of::<u32>();
+        buf[value_sz..value_sz + 4].copy_from_slice(&(TOMBSTONE - 1).to_be_bytes());
+        let err = MemoryStore::import(&mut buf.as_slice()).unwrap_err();
+        assert!(matches!(err, CaskaError::Truncated));
+
+        let options = Options::new().max_value_size(16);
+        let err = MemoryStore::import_with(&mut buf.as_slice(), options).unwrap_err();
+        assert!(matches!(err, CaskaError::ValueTooLarge));
+    }
+
+    #[test]
+    fn memory_store_import_with_keeps_the_callers_options() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world".repeat(100)).unwrap();
+        let mut buf = vec![];
+        store.export(&mut buf).unwrap();
+
+        let options = Options::new().compression(Compression::Lz4);
+        let imported = MemoryStore::import_with(&mut buf.as_slice(), options).unwrap();
+        assert_eq!(Compression::Lz4, imported.options.compression);
+        assert!(imported.file.size() < store.file.size());
+        assert_eq!(
+            Some("world".repeat(100).into_bytes()),
+            imported.get("hello")
+        );
+    }
+
+    #[test]
+    fn memory_store_import_rejects_other_streams() {
+        let err = MemoryStore::import(&mut b"hello world".as_slice()).unwrap_err();
+        assert!(matches!(err, CaskaError::InvalidMagic));
+    }
+
+    #[test]
+    fn memory_store_merge_keeps_recent_tombstones() {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let options = Options::new()
+            .clock(clock.clone())
+            .tombstone_ttl(Duration::from_secs(60));
+        let mut store = MemoryStore::with_options(options);
+        store.put("old", "value").unwrap();
+        store.delete("old").unwrap();
+        clock.advance(120);
+        store.put("fresh", "value").unwrap();
+        store.delete("fresh").unwrap();
+        store.put("live", "value").unwrap();
+
+        store.merge().unwrap();
+
+        let tombstones: Vec<_> = store
+            .iter_entries()
+            .map(Result::unwrap)
+            .filter(KeyValueEntry::is_tombstone)
+            .map(|entry| (entry.key, entry.tstamp))
+            .collect();
+        assert_eq!(vec![(b"fresh".to_vec(), 1_700_000_120)], tombstones);
+        assert_eq!(Some(b"value".to_vec()), store.get("live"));
+        assert_eq!(None, store.get("fresh"));
+
+        clock.advance(120);
+        store.merge().unwrap();
+        assert!(!store
+            .iter_entries()
+            .any(|entry| entry.unwrap().is_tombstone()));
+    }
+
+    #[test]
+    fn keydir_loaded_from_hint_matches_scanned_keydir() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "again").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.delete("last_name").unwrap();
+
+        let mut hint = vec![];
+        write_hint(
+            &store.keydir,
+            store.file.size(),
+            store.seq,
+            &mut hint,
+            Endianness::Big,
+        )
+        .unwrap();
+
+        let (want, _) = load_keydir(
+            store.file.id,
+            &store.file.data,
+            Endianness::Big,
+            store.now(),
+        )
+        .unwrap();
+        let got = load_hint(store.file.id, &hint, store.file.size(), Endianness::Big).unwrap();
+        assert_eq!(Some((want, store.seq)), got);
+
+        let stale = load_hint(store.file.id, &hint, store.file.size() - 1, Endianness::Big);
+        assert_eq!(None, stale.unwrap());
+    }
+
+    #[test]
+    fn load_hint_rejects_a_hint_cut_short() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        let mut hint = vec![];
+        write_hint(
+            &store.keydir,
+            store.file.size(),
+            store.seq,
+            &mut hint,
+            Endianness::Big,
+        )
+        .unwrap();
+
+        // Dropping the trailer and the last record, "hello", leaves a hint
+        // that would otherwise parse cleanly.
+        let record = 3 * size_of::<u64>() + size_of::<u8>() + 3 * size_of::<u32>() + 5;
+        for cut in [1, size_of::<u32>(), size_of::<u32>() + record, hint.len()] {
+            let torn = &hint[..hint.len() - cut];
+            let got = load_hint(store.file.id, torn, store.file.size(), Endianness::Big);
+            assert_eq!(None, got.unwrap(), "cut {cut}");
+        }
+    }
+
+    #[test]
+    fn memory_store_can_be_loaded_from_a_hint_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let hint_path = dir.path().join("caska.hint");
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.write_hint_file(&hint_path).unwrap();
+
+        let loaded = MemoryStore::load_from_hint(store.file.data.clone(), &hint_path).unwrap();
+
+        assert_eq!(store.len(), loaded.len());
+        assert_eq!(b"world".to_vec(), loaded.get("hello").unwrap());
+        assert_eq!(b"john".to_vec(), loaded.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn load_from_hint_with_keeps_the_callers_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let hint_path = dir.path().join("caska.hint");
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.write_hint_file(&hint_path).unwrap();
+        let current = store.file.data.clone();
+        store.put("last_name", "smith").unwrap();
+        let stale = store.file.data.clone();
+
+        // The second load finds the hint stale and scans instead.
+        for data in [current, stale] {
+            let options = Options::new().max_entries(2);
+            let mut loaded = MemoryStore::load_from_hint_with(data, &hint_path, options).unwrap();
+            loaded.put("age", 42).unwrap();
+            assert_eq!(2, loaded.len());
+            assert_eq!(Some(2), loaded.options.max_entries);
+        }
+    }
+
+    #[test]
+    fn hinted_load_keeps_the_seq_of_a_trailing_tombstone() {
+        let dir = tempfile::tempdir().unwrap();
+        let hint_path = dir.path().join("caska.hint");
+        // Every write lands in the same second, so only `seq` orders them.
+        let options = Options::new().clock(Arc::new(MockClock::new(1_700_000_000)));
+        let mut store = MemoryStore::with_options(options.clone());
+        store.put("a", "1").unwrap();
+        store.delete("a").unwrap();
+        store.write_hint_file(&hint_path).unwrap();
+
+        let mut loaded =
+            MemoryStore::load_from_hint_with(store.file.data.clone(), &hint_path, options.clone())
+                .unwrap();
+        loaded.put("a", "2").unwrap();
+
+        let replayed = MemoryStore::from_bytes_with(loaded.file.data.clone(), options).unwrap();
+        assert_eq!(Some(b"2".to_vec()), replayed.get("a"));
+    }
+
+    #[test]
+    fn memory_store_expires_keys_after_their_ttl() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+
+        store
+            .put_with_ttl("hello", "again", Duration::ZERO)
+            .unwrap();
+        store
+            .put_with_ttl("first_name", "john", Duration::from_secs(3600))
+            .unwrap();
+
+        assert_eq!(None, store.get("hello"));
+        assert!(!store.contains_key("hello"));
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn len_skips_expired_keys() {
+        let mut store = MemoryStore::new();
+        store
+            .put_with_ttl("hello", "world", Duration::ZERO)
+            .unwrap();
+        assert_eq!(0, store.len());
+        assert!(store.is_empty());
+        assert_eq!(0, store.stats().live_keys);
+
+        store.put("first_name", "john").unwrap();
+        assert_eq!(1, store.len());
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn put_with_ttl_saturates_huge_ttls() {
+        let mut store = MemoryStore::new();
+        store.put_with_ttl("hello", "world", Duration::MAX).unwrap();
+
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn expired_keys_are_dropped_on_merge_and_reload() {
+        let mut store = MemoryStore::new();
+        store
+            .put_with_ttl("hello", "world", Duration::ZERO)
+            .unwrap();
+        store
+            .put_with_ttl("first_name", "john", Duration::from_secs(3600))
+            .unwrap();
+
+        let replayed = MemoryStore::from_bytes(store.file.data.clone()).unwrap();
+        assert_eq!(1, replayed.len());
+
+        store.merge().unwrap();
+        assert_eq!(1, store.len());
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn decode_values_with_from_bytes() {
+        assert_eq!(42, u32::from_bytes(&[0, 0, 0, 42]).unwrap());
+        assert!(u32::from_bytes(&[0, 42]).is_err());
+        assert_eq!("hello", String::from_bytes(b"hello").unwrap());
+        assert!(String::from_bytes(&[0xff, 0xfe]).is_err());
+        assert_eq!(vec![1, 2, 3], Vec::<u8>::from_bytes(&[1, 2, 3]).unwrap());
+    }
+
+    #[test]
+    fn memory_store_can_get_typed_values() {
+        let mut store = MemoryStore::new();
+        store.put("answer", 42).unwrap();
+        store.put("hello", "world").unwrap();
+
+        assert_eq!(Some(42), store.get_as::<_, u32>("answer").unwrap());
+        assert_eq!(
+            Some("world".to_string()),
+            store.get_as::<_, String>("hello").unwrap()
+        );
+        assert_eq!(None, store.get_as::<_, u32>("missing").unwrap());
+        assert!(store.get_as::<_, u32>("hello").is_err());
+    }
+
+    #[test]
+    fn memory_store_put_many_matches_individual_puts() {
+        let key_value_pairs = vec![
+            ("hello", "world"),
+            ("first_name", "john"),
+            ("last_name", "smith"),
+        ];
+        let mut batched = MemoryStore::new();
+        let mut individual = MemoryStore::new();
+
+        batched.put_many(key_value_pairs.clone()).unwrap();
+        for (key, value) in key_value_pairs.clone() {
+            individual.put(key, value).unwrap();
+        }
+
+        assert_eq!(individual.len(), batched.len());
+        assert_eq!(individual.file.size(), batched.file.size());
+        for (key, _) in key_value_pairs {
+            assert_eq!(individual.get(key), batched.get(key));
+        }
+    }
+
+    #[test]
+    fn memory_store_get_many_preserves_key_order() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("last_name", "smith").unwrap();
+
+        let got = store.get_many(vec!["last_name", "missing", "hello", "other"]);
+
+        let want = vec![Some(b"smith".to_vec()), None, Some(b"world".to_vec()), None];
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn memory_store_compare_and_swap() {
+        let mut store = MemoryStore::new();
+
+        assert!(store.compare_and_swap("hello", None, "world").unwrap());
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+
+        assert!(!store.compare_and_swap("hello", None, "again").unwrap());
+        assert!(!store
+            .compare_and_swap("hello", Some(b"other".as_slice()), "again")
+            .unwrap());
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+
+        assert!(store
+            .compare_and_swap("hello", Some(b"world".as_slice()), "again")
+            .unwrap());
+        assert_eq!(b"again".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_increment_starts_at_zero() {
+        let mut store = MemoryStore::new();
+
+        assert_eq!(5, store.increment("counter", 5).unwrap());
+        assert_eq!(3, store.increment("counter", -2).unwrap());
+        assert_eq!(3i64.to_bytes(), store.get("counter").unwrap());
+    }
+
+    #[test]
+    fn memory_store_increment_wraps_around() {
+        let mut store = MemoryStore::new();
+        store.put("counter", i64::MAX).unwrap();
+
+        assert_eq!(i64::MIN, store.increment("counter", 1).unwrap());
+    }
+
+    #[test]
+    fn memory_store_increment_rejects_non_integer_values() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+
+        assert!(store.increment("hello", 1).is_err());
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_merge_value_appends_operands() {
+        let mut store = MemoryStore::new();
+        let concat = |current: Option<&[u8]>, operand: &[u8]| {
+            [current.unwrap_or_default(), operand].concat()
+        };
+
+        store.merge_value("log", "a", concat).unwrap();
+        store.merge_value("log", "b", concat).unwrap();
+        store.merge_value("log", "c", concat).unwrap();
+
+        assert_eq!(b"abc".to_vec(), store.get("log").unwrap());
+    }
+
+    #[test]
+    fn memory_store_merge_value_adds_numbers() {
+        let mut store = MemoryStore::new();
+        let add = |current: Option<&[u8]>, operand: &[u8]| {
+            let current = current.map_or(0, |bytes| u64::from_be_bytes(bytes.try_into().unwrap()));
+            let operand = u64::from_be_bytes(operand.try_into().unwrap());
+            (current + operand).to_bytes()
+        };
+
+        store.merge_value("total", 40u64, add).unwrap();
+        store.merge_value("total", 2u64, add).unwrap();
+
+        assert_eq!(42u64.to_bytes(), store.get("total").unwrap());
+    }
+
+    #[test]
+    fn memory_store_append_creates_then_extends_values() {
+        let mut store = MemoryStore::new();
+
+        store.append("log", b"hello").unwrap();
+        assert_eq!(b"hello".to_vec(), store.get("log").unwrap());
+
+        store.append("log", b" world").unwrap();
+        assert_eq!(b"hello world".to_vec(), store.get("log").unwrap());
+    }
+
+    #[test]
+    fn memory_store_put_if_absent_never_overwrites() {
+        let mut store = MemoryStore::new();
+
+        assert!(store.put_if_absent("lock", "owner-1").unwrap());
+        assert!(!store.put_if_absent("lock", "owner-2").unwrap());
+        assert_eq!(b"owner-1".to_vec(), store.get("lock").unwrap());
+
+        store.delete("lock").unwrap();
+        assert!(store.put_if_absent("lock", "owner-2").unwrap());
+        assert_eq!(b"owner-2".to_vec(), store.get("lock").unwrap());
+    }
+
+    #[test]
+    fn memory_store_get_or_insert_with_only_computes_absent_values() {
+        let mut store = MemoryStore::new();
+        let mut calls = 0;
+
+        let got = store
+            .get_or_insert_with("hello", || {
+                calls += 1;
+                "world"
+            })
+            .unwrap();
+        assert_eq!(b"world".to_vec(), got);
+        assert_eq!(1, calls);
+
+        let got = store
+            .get_or_insert_with("hello", || {
+                calls += 1;
+                "again"
+            })
+            .unwrap();
+        assert_eq!(b"world".to_vec(), got);
+        assert_eq!(1, calls);
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_touch_refreshes_the_timestamp() {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let options = Options::new()
+            .clock(clock.clone())
+            .compression(Compression::Lz4);
+        let mut store = MemoryStore::with_options(options);
+        let value = "a".repeat(100);
+        store.put("hello", value.as_str()).unwrap();
+
+        clock.advance(60);
+        assert!(store.touch("hello").unwrap());
+        assert!(!store.touch("missing").unwrap());
+
+        let got = store.get_entry("hello").unwrap();
+        assert_eq!(1_700_000_060, got.tstamp);
+        assert_eq!(value.into_bytes(), got.value);
+    }
+
+    #[test]
+    fn memory_store_get_into_reuses_the_buffer() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("empty", "").unwrap();
+
+        let mut buf = vec![];
+        assert_eq!(Some(5), store.get_into("hello", &mut buf));
+        assert_eq!(b"world".to_vec(), buf);
+        assert_eq!(Some(4), store.get_into("first_name", &mut buf));
+        assert_eq!(b"john".to_vec(), buf);
+        assert_eq!(Some(0), store.get_into("empty", &mut buf));
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"stale");
+        assert_eq!(None, store.get_into("missing", &mut buf));
+        assert_eq!(b"stale".to_vec(), buf);
+    }
+
+    #[test]
+    fn memory_store_get_entry_returns_write_timestamp() {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let mut store = MemoryStore::with_options(Options::new().clock(clock));
+
+        store.put("hello", "world").unwrap();
+
+        let got = store.get_entry("hello").unwrap();
+        assert_eq!(b"world".to_vec(), got.value);
+        assert_eq!(1_700_000_000, got.tstamp);
+        assert_eq!(None, store.get_entry("missing"));
+    }
+
+    #[test]
+    fn memory_store_scans_keys_by_prefix() {
+        let mut store = MemoryStore::new();
+        store.put("user:1:name", "john").unwrap();
+        store.put("user:1:email", "john@example.com").unwrap();
+        store.put("user:2:name", "jane").unwrap();
+        store.put("group:1:name", "admins").unwrap();
+        store.put("user:1:name", "johnny").unwrap();
+
+        let got: Vec<_> = store.scan_prefix(b"user:1:").collect();
+
+        let want = vec![
+            (b"user:1:email".as_slice(), b"john@example.com".to_vec()),
+            (b"user:1:name".as_slice(), b"johnny".to_vec()),
+        ];
+        assert_eq!(want, got);
+        assert_eq!(3, store.scan_prefix(b"user:").count());
+        assert_eq!(0, store.scan_prefix(b"missing:").count());
+    }
+
+    #[test]
+    fn memory_store_scans_a_range_in_key_order() {
+        let mut store = MemoryStore::new();
+        for key in ["d", "b", "e", "a", "c"] {
+            store.put(key, key.to_uppercase().as_str()).unwrap();
+        }
+        let keys = |range: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            range.into_iter().map(|(key, _)| key).collect()
+        };
+
+        let got: Vec<_> = store.scan_range(b"b".to_vec()..b"d".to_vec()).collect();
+        assert_eq!(
+            vec![
+                (b"b".to_vec(), b"B".to_vec()),
+                (b"c".to_vec(), b"C".to_vec())
+            ],
+            got
+        );
+
+        let got = keys(store.scan_range(b"b".to_vec()..=b"d".to_vec()).collect());
+        assert_eq!(vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()], got);
+
+        let got = keys(store.scan_range(b"d".to_vec()..).collect());
+        assert_eq!(vec![b"d".to_vec(), b"e".to_vec()], got);
+
+        assert_eq!(0, store.scan_range(b"x".to_vec()..b"z".to_vec()).count());
+    }
+
+    #[test]
+    fn memory_store_reports_its_smallest_and_largest_keys() {
+        let mut store = MemoryStore::new();
+        assert_eq!(None, store.first_key());
+        assert_eq!(None, store.last_key());
+
+        for key in ["d", "b", "e", "a", "c"] {
+            store.put(key, "value").unwrap();
+        }
+        assert_eq!(Some(b"a".to_vec()), store.first_key());
+        assert_eq!(Some(b"e".to_vec()), store.last_key());
+
+        store.delete("a").unwrap();
+        store.delete("e").unwrap();
+        assert_eq!(Some(b"b".to_vec()), store.first_key());
+        assert_eq!(Some(b"d".to_vec()), store.last_key());
+
+        for key in ["b", "c", "d"] {
+            store.delete(key).unwrap();
+        }
+        assert_eq!(None, store.first_key());
+        assert_eq!(None, store.last_key());
+    }
+
+    #[test]
+    fn memory_store_streams_large_values_in_chunks() {
+        /// Records the size of every write it receives.
+        #[derive(Default)]
+        struct ChunkRecorder {
+            data: Vec<u8>,
+            writes: Vec<usize>,
+        }
+
+        impl Write for ChunkRecorder {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.data.extend_from_slice(buf);
+                self.writes.push(buf.len());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let value: Vec<u8> = (0..3 * STREAM_CHUNK_SIZE + 10).map(|i| i as u8).collect();
+        for compression in [Compression::None, Compression::Zstd] {
+            let mut store = MemoryStore::with_options(Options::new().compression(compression));
+            store.put("hello", value.clone()).unwrap();
+
+            let mut out = ChunkRecorder::default();
+            let written = store.read_value_streaming("hello", &mut out).unwrap();
+
+            assert_eq!(Some(value.len() as u64), written);
+            assert_eq!(value, out.data);
+            assert_eq!(4, out.writes.len());
+            assert!(out.writes.iter().all(|&size| size <= STREAM_CHUNK_SIZE));
+        }
+
+        let store = MemoryStore::new();
+        let written = store.read_value_streaming("missing", &mut vec![]).unwrap();
+        assert_eq!(None, written);
+    }
+
+    #[test]
+    fn scan_all_reads_values_only_as_far_as_it_is_driven() {
+        use testing::values_read;
+
+        let mut store = MemoryStore::new();
+        for key in ["a", "b", "c", "d", "e"] {
+            store.put(key, key.to_uppercase().as_str()).unwrap();
+        }
+
+        let before = values_read();
+        let found = store.scan_all().find(|(key, _)| key == b"c");
+        assert_eq!(Some((b"c".to_vec(), b"C".to_vec())), found);
+        assert_eq!(3, values_read() - before);
+
+        let taken = store.scan_all().take(2).count();
+        assert_eq!(2, taken);
+        assert_eq!(5, values_read() - before);
+
+        assert_eq!(5, store.scan_all().count());
+    }
+
+    #[test]
+    fn memory_store_iterates_in_reverse_key_order() {
+        let mut store = MemoryStore::new();
+        for key in ["d", "b", "e", "a", "c"] {
+            store.put(key, key.to_uppercase().as_str()).unwrap();
+        }
+        store.delete("e").unwrap();
+
+        let mut forward: Vec<_> = store.scan_range(..).collect();
+        forward.reverse();
+        assert_eq!(forward, store.iter_rev().collect::<Vec<_>>());
+
+        let got: Vec<_> = store
+            .scan_range_rev(b"b".to_vec()..=b"d".to_vec())
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(vec![b"d".to_vec(), b"c".to_vec(), b"b".to_vec()], got);
+    }
+
+    #[test]
+    fn memory_store_recovers_from_a_truncated_entry() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        let valid_len = store.file.size();
+        store.put("first_name", "john").unwrap();
+        let mut data = store.file.data.clone();
+        data.truncate(data.len() - 3);
+
+        let (recovered, recovery) =
+            MemoryStore::recover_from_bytes(data.clone(), Options::default()).unwrap();
+
+        let want = RecoveryInfo {
+            bytes_truncated: data.len() - valid_len,
+            entries_loaded: 1,
+            last_seq: 1,
+        };
+        assert_eq!(want, recovery);
+        assert_eq!(valid_len, recovered.file.size());
+        assert_eq!(b"world".to_vec(), recovered.get("hello").unwrap());
+        assert_eq!(None, recovered.get("first_name"));
+    }
+
+    #[test]
+    fn index_keydir_agrees_with_a_full_load() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "there").unwrap();
+        store.delete("first_name").unwrap();
+        let mut data = store.file.data.clone();
+        testing::append_torn_entry(&mut data);
+
+        let want = load_keydir(1, &data, Endianness::default(), 0).unwrap();
+        let mut reader = Cursor::new(&data);
+        let got =
+            index_keydir(1, &mut reader, data.len() as u64, Endianness::default(), 0).unwrap();
+        assert_eq!(want, got);
+    }
+
+    #[test]
+    fn memory_store_reports_repairing_a_torn_tail() {
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = events.clone();
+        let options = Options::new().on_event(Box::new(move |event| {
+            recorded.lock().unwrap().push(event.clone());
+        }));
+        let valid = testing::valid_stream(&[("hello", "world")]);
+        let store = MemoryStore::from_bytes_with(valid.clone(), options.clone()).unwrap();
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(valid, store.file.data);
+
+        let mut data = valid.clone();
+        let torn = testing::append_torn_entry(&mut data);
+        let store = MemoryStore::from_bytes_with(data, options).unwrap();
+
+        let want = vec![StoreEvent::Repaired {
+            truncated_bytes: torn,
+        }];
+        assert_eq!(want, *events.lock().unwrap());
+        assert_eq!(valid, store.file.data);
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_does_not_skip_corrupted_entries() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        let mut data = store.file.data.clone();
+        data[FileHeader::SIZE + 5] ^= 0xff;
+
+        let err = MemoryStore::from_bytes(data).unwrap_err();
+        assert!(matches!(err, CaskaError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn memory_store_enforces_max_key_and_value_sizes() {
+        let options = Options::new().max_key_size(5).max_value_size(5);
+        let mut store = MemoryStore::with_options(options);
+
+        store.put("hello", "world").unwrap();
+
+        let err = store.put("hello!", "world").unwrap_err();
+        assert!(matches!(err, CaskaError::KeyTooLarge));
+        let err = store.put("hello", "world!").unwrap_err();
+        assert!(matches!(err, CaskaError::ValueTooLarge));
+        let err = store
+            .put_many(vec![("a", "1"), ("b", "too long")])
+            .unwrap_err();
+        assert!(matches!(err, CaskaError::ValueTooLarge));
+
+        assert_eq!(1, store.len());
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn put_batch_atomic_leaves_the_store_untouched_on_error() {
+        let options = Options::new().max_value_size(5).max_file_size(64);
+        let mut store = MemoryStore::with_options(options);
+        store.put("hello", "world").unwrap();
+        let (data, keydir, seq) = (store.file.data.clone(), store.keydir.clone(), store.seq);
+
+        let err = store
+            .put_batch_atomic(vec![("a", "1"), ("hello", "there"), ("b", "too long")])
+            .unwrap_err();
+        assert!(matches!(err, CaskaError::ValueTooLarge));
+        assert_eq!(data, store.file.data);
+        assert_eq!(keydir, store.keydir);
+        assert_eq!(seq, store.seq);
+        assert!(store.files.is_empty());
+
+        store
+            .put_batch_atomic(vec![("a", "1"), ("hello", "there")])
+            .unwrap();
+        assert_eq!(b"1".to_vec(), store.get("a").unwrap());
+        assert_eq!(b"there".to_vec(), store.get("hello").unwrap());
+        assert_eq!(seq + 2, store.seq);
+    }
+
+    #[test]
+    fn memory_store_can_be_cleared() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(32));
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        let file_id = store.file.id;
+
+        store.clear().unwrap();
+
+        assert!(store.is_empty());
+        assert!(store.files.is_empty());
+        assert!(store.file.is_empty());
+        assert_ne!(file_id, store.file.id);
+        assert_eq!(None, store.get("hello"));
+        assert_eq!(None, store.get("first_name"));
+    }
+
+    #[test]
+    fn memory_store_insert_returns_the_previous_value() {
+        let mut store = MemoryStore::new();
+
+        assert_eq!(None, store.insert("hello", "world").unwrap());
+        assert_eq!(
+            Some(b"world".to_vec()),
+            store.insert("hello", "again").unwrap()
+        );
+        assert_eq!(b"again".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_iterates_over_every_entry_in_write_order() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        store.put("hello", "world").unwrap();
+        store.put("hello", "again").unwrap();
+        store.delete("hello").unwrap();
+
+        let got: Vec<_> = store.iter_entries().map(Result::unwrap).collect();
+
+        assert_eq!(3, got.len());
+        assert_eq!(b"world".to_vec(), got[0].value);
+        assert_eq!(b"again".to_vec(), got[1].value);
+        assert!(got[2].is_tombstone());
+        assert!(got.iter().all(|entry| entry.key == b"hello"));
+    }
+
+    #[test]
+    fn get_versions_lists_every_overwrite_until_a_merge() {
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let options = Options::new()
+            .clock(clock.clone())
+            .compression(Compression::Lz4)
+            .max_file_size(64);
+        let mut store = MemoryStore::with_options(options);
+        for value in ["one", "two", "three"] {
+            store.put("hello", value.repeat(10)).unwrap();
+            store.put("first_name", "john").unwrap();
+            clock.advance(1);
+        }
+
+        let want: Vec<_> = [(0, "one"), (1, "two"), (2, "three")]
+            .into_iter()
+            .map(|(age, value)| (1_700_000_000 + age, value.repeat(10).into_bytes()))
+            .collect();
+        assert_eq!(want, store.get_versions("hello"));
+
+        store.delete("first_name").unwrap();
+        store.merge().unwrap();
+        assert_eq!(want[2..], store.get_versions("hello"));
+        assert!(store.get_versions("first_name").is_empty());
+    }
+
+    #[test]
+    fn entries_iterator_stops_after_an_error() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.file.data[FileHeader::SIZE + 5] ^= 0xff;
+
+        let got: Vec<_> = store.iter_entries().collect();
+
+        assert_eq!(1, got.len());
+        assert!(matches!(got[0], Err(CaskaError::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn bulk_load_matches_sequential_puts() {
+        let pairs: Vec<(String, String)> = (0..50)
+            .map(|i| (format!("key-{}", i % 40), format!("value-{i}")))
+            .collect();
+        let options = Options::new()
+            .clock(Arc::new(MockClock::new(1_700_000_000)))
+            .max_file_size(256);
+
+        let loaded = MemoryStore::bulk_load(pairs.clone(), options.clone()).unwrap();
+        let mut sequential = MemoryStore::with_options(options);
+        for (key, value) in &pairs {
+            sequential.put(key.as_str(), value.as_str()).unwrap();
+        }
+
+        assert_eq!(sequential.keydir, loaded.keydir);
+        assert_eq!(sequential.file.data, loaded.file.data);
+        assert_eq!(sequential.files.len(), loaded.files.len());
+        assert_eq!(
+            sequential.scan_range(..).collect::<Vec<_>>(),
+            loaded.scan_range(..).collect::<Vec<_>>()
+        );
+        assert_eq!(b"value-49".to_vec(), loaded.get("key-9").unwrap());
+    }
+
+    #[test]
+    fn memory_store_uses_the_configured_clock() {
+        let clock = Arc::new(MockClock::new(42));
+        let mut store = MemoryStore::with_options(Options::new().clock(clock.clone()));
+
+        store.put("hello", "world").unwrap();
+        store
+            .put_with_ttl("first_name", "john", Duration::from_secs(10))
+            .unwrap();
+
+        let entry = store.iter_entries().next().unwrap().unwrap();
+        assert_eq!(42, entry.tstamp);
+        assert_eq!(b"john".to_vec(), store.get("first_name").unwrap());
+
+        clock.advance(10);
+        assert_eq!(None, store.get("first_name"));
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn memory_store_orders_same_second_writes_by_sequence_number() {
+        let clock = Arc::new(MockClock::new(42));
+        let options = Options::new().clock(clock);
+        let mut store = MemoryStore::with_options(options.clone());
+        store.put("hello", "world").unwrap();
+        store.put("hello", "there").unwrap();
+
+        let mut reloaded = MemoryStore::from_bytes_with(store.file.data.clone(), options).unwrap();
+        assert_eq!(b"there".to_vec(), reloaded.get("hello").unwrap());
+
+        reloaded.put("hello", "again").unwrap();
+        assert_eq!((42, 3), reloaded.keydir[b"hello".as_slice()].version());
+    }
+
+    #[test]
+    fn memory_store_iterates_over_live_values() {
+        let mut store = MemoryStore::new();
+        store.put("a", "1").unwrap();
+        store.put("b", "2").unwrap();
+        store.put("a", "3").unwrap();
+        store.delete("b").unwrap();
+        store.put("c", "4").unwrap();
+
+        let got: Vec<_> = store.values().collect();
+
+        assert_eq!(vec![b"3".to_vec(), b"4".to_vec()], got);
+    }
+
+    #[test]
+    fn memory_store_folds_over_live_pairs() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("last_name", "smith").unwrap();
+        store.put("hello", "again!").unwrap();
+
+        let got = store.fold(0, |total, _, value| total + value.len());
+
+        let want: usize = store.values().map(|value| value.len()).sum();
+        assert_eq!(want, got);
+        assert_eq!(15, got);
+    }
+
+    #[test]
+    fn compact_if_needed_merges_only_past_the_threshold() {
+        let mut store = MemoryStore::with_options(Options::new().merge_threshold(0.5));
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+        store.put("hello", "world").unwrap();
+
+        let size_before = store.stats().total_bytes;
+        assert!(!store.compact_if_needed().unwrap());
+        assert_eq!(size_before, store.stats().total_bytes);
+
+        for _ in 0..5 {
+            store.put("hello", "world").unwrap();
+        }
+        assert!(store.compact_if_needed().unwrap());
+        assert!(store.stats().fragmentation_ratio.abs() < f64::EPSILON);
+        assert_eq!(b"world".to_vec(), store.get("hello").unwrap());
+    }
+
+    #[test]
+    fn stats_count_bytes_written_and_reclaimed_over_the_handle_lifetime() {
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(64));
+        let entry_size = KeyValueEntry::new(0, "hello", "world").size();
+        for i in 1..=5 {
+            store.put("hello", "world").unwrap();
+            assert_eq!(i * entry_size, store.stats().total_bytes_written);
+        }
+        assert_eq!(0, store.stats().total_bytes_reclaimed);
+
+        let report = store.merge().unwrap();
+        let stats = store.stats();
+        assert!(report.reclaimed_bytes > 0);
+        assert_eq!(report.reclaimed_bytes, stats.total_bytes_reclaimed);
+        assert_eq!(5 * entry_size, stats.total_bytes_written);
+
+        store.put("hello", "world").unwrap();
+        store.merge().unwrap();
+        let stats = store.stats();
+        assert_eq!(6 * entry_size, stats.total_bytes_written);
+        assert!(stats.total_bytes_reclaimed > report.reclaimed_bytes);
+    }
+
+    #[test]
+    fn compact_to_writes_a_smaller_copy_and_leaves_the_source_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_path = dir.path().join("compacted.data");
+        let mut store = MemoryStore::with_options(Options::new().max_file_size(128));
+        for i in 0..20u32 {
+            store.put(i % 5, i).unwrap();
+        }
+        store.delete(0u32).unwrap();
+        let (files_before, size_before) = (store.file_count(), store.total_bytes());
+
+        store.compact_to(&out_path).unwrap();
+
+        assert_eq!(files_before, store.file_count());
+        assert_eq!(size_before, store.total_bytes());
+        let data = fs::read(&out_path).unwrap();
+        assert!(data.len() < size_before);
+        let compacted = MemoryStore::from_bytes(data).unwrap();
+        assert_eq!(
+            store.scan_all().collect::<Vec<_>>(),
+            compacted.scan_all().collect::<Vec<_>>()
+        );
+        assert_eq!(None, compacted.get(0u32));
+    }
+
+    #[test]
+    fn memory_store_stats_track_fragmentation() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+
+        let stats = store.stats();
+        assert_eq!(2, stats.live_keys);
+        assert_eq!(stats.total_bytes, FileHeader::SIZE + stats.live_bytes);
+        assert!(stats.fragmentation_ratio.abs() < f64::EPSILON);
+
+        for _ in 0..5 {
+            store.put("hello", "world").unwrap();
+        }
+        let fragmented = store.stats();
+        assert_eq!(2, fragmented.live_keys);
+        assert_eq!(stats.live_bytes, fragmented.live_bytes);
+        assert!(fragmented.fragmentation_ratio > 0.5);
+
+        store.merge().unwrap();
+        let merged = store.stats();
+        assert_eq!(merged.total_bytes, FileHeader::SIZE + merged.live_bytes);
+        assert!(merged.fragmentation_ratio.abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn memory_store_buckets_value_sizes() {
+        let mut store = MemoryStore::new();
+        for (i, size) in [0, 16, 17, 32, 33, 64, 1000].into_iter().enumerate() {
+            store.put(i as u32, "a".repeat(size)).unwrap();
+        }
+
+        let want = BTreeMap::from([(16, 2), (32, 2), (64, 2), (1024, 1)]);
+        assert_eq!(want, store.size_histogram());
+    }
+
+    #[test]
+    fn file_header_round_trips() {
+        for byte_order in [Endianness::Big, Endianness::Little] {
+            for checksum in [ChecksumKind::Crc32, ChecksumKind::XxHash64] {
+                let header = FileHeader::new(byte_order, checksum);
+                let bytes = header.encode();
+
+                assert_eq!(b"CASK".as_slice(), &bytes[..4]);
+                assert_eq!(header, FileHeader::decode(&bytes).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn memory_store_validates_entries_with_the_headers_checksum() {
+        let options = Options::new().checksum(ChecksumKind::XxHash64);
+        let mut store = MemoryStore::with_options(options);
+        store.put("hello", "world").unwrap();
+        let mut data = store.file.data.clone();
+
+        // Readers go by the header, whatever their own options say.
+        let store = MemoryStore::from_bytes(data.clone()).unwrap();
+        assert_eq!(ChecksumKind::XxHash64, store.file.checksum);
+        assert_eq!(Some(b"world".to_vec()), store.get("hello"));
+
+        data[FileHeader::SIZE] ^= 0xff;
+        let err = MemoryStore::from_bytes(data).unwrap_err();
+        assert!(matches!(err, CaskaError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn memory_store_round_trips_little_endian_files() {
+        let options = Options::new().byte_order(Endianness::Little);
+        let mut store = MemoryStore::with_options(options.clone());
+        store.put("hello", "world").unwrap();
+        store.delete("hello").unwrap();
+        store.put("first_name", "john").unwrap();
+        let data = store.file.data.clone();
+
+        // ksz of the first entry, after its crc, tstamp, seq, expiry and flags.
+        let ksz_pos = FileHeader::SIZE + 4 + 8 + 8 + 8 + 1;
+        assert_eq!([5, 0, 0, 0], data[ksz_pos..ksz_pos + 4]);
+
+        let store = MemoryStore::from_bytes_with(data.clone(), options).unwrap();
+        assert_eq!(None, store.get("hello"));
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name"));
+
+        let err = MemoryStore::from_bytes(data).unwrap_err();
+        assert!(matches!(
+            err,
+            CaskaError::ByteOrderMismatch {
+                found: Endianness::Little,
+                expected: Endianness::Big,
+            }
+        ));
+    }
+
+    #[test]
+    fn memory_store_rejects_data_without_a_valid_header() {
+        let err = MemoryStore::from_bytes(b"NOPE\x00\x01".to_vec()).unwrap_err();
+        assert!(matches!(err, CaskaError::InvalidMagic));
+
+        let err = MemoryStore::from_bytes(vec![]).unwrap_err();
+        assert!(matches!(err, CaskaError::InvalidMagic));
+
+        let mut data = MemoryStore::new().file.data;
+        data[5] = 99;
+        let err = MemoryStore::from_bytes(data).unwrap_err();
+        assert!(matches!(
+            err,
+            CaskaError::UnsupportedVersion {
+                found: 99,
+                expected: FORMAT_VERSION
+            }
+        ));
+    }
+
+    #[test]
+    fn memory_store_rejects_files_from_the_previous_format_version() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        let mut data = store.file.data.clone();
+        data[4..6].copy_from_slice(&(FORMAT_VERSION - 1).to_be_bytes());
+
+        let err = MemoryStore::from_bytes(data).unwrap_err();
+        assert!(matches!(
+            err,
+            CaskaError::UnsupportedVersion { found, expected: FORMAT_VERSION }
+                if found == FORMAT_VERSION - 1
+        ));
+    }
+
+    #[test]
+    fn memory_store_with_bloom_filter_never_hides_present_keys() {
+        let options = Options::new().enable_bloom_filter(100);
+        let mut store = MemoryStore::with_options(options.clone());
+        for i in 0..100u32 {
+            store.put(i, i).unwrap();
+        }
+        store.put_many((100..110u32).map(|i| (i, i))).unwrap();
+
+        assert!((0..110u32).all(|i| store.contains_key(i)));
+        assert!((0..110u32).all(|i| store.get(i) == Some(i.to_bytes())));
+        assert!((110..1000u32).all(|i| !store.contains_key(i)));
+
+        store.delete(0u32).unwrap();
+        store.merge().unwrap();
+        assert!(!store.contains_key(0u32));
+        assert!((1..110u32).all(|i| store.contains_key(i)));
+
+        let reloaded = MemoryStore::from_bytes_with(store.file.data.clone(), options).unwrap();
+        assert!(reloaded.bloom.is_some());
+        assert!((1..110u32).all(|i| reloaded.contains_key(i)));
+        assert!(!reloaded.contains_key(0u32));
+    }
 }
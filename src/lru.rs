@@ -0,0 +1,69 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Tracks the order keys were last used in, so the least recently used one
+/// can be evicted.
+#[derive(Debug, Default)]
+pub(crate) struct Lru {
+    /// Last access tick per key.
+    ticks: HashMap<Vec<u8>, u64>,
+    /// Keys ordered by their last access tick.
+    order: BTreeMap<u64, Vec<u8>>,
+    next_tick: u64,
+}
+
+impl Lru {
+    /// Marks `key` as the most recently used.
+    pub(crate) fn touch(&mut self, key: &[u8]) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+
+        match self.ticks.get_mut(key) {
+            Some(last) => {
+                let key = self.order.remove(last).expect("lru order tracks every key");
+                *last = tick;
+                self.order.insert(tick, key);
+            }
+            None => {
+                self.ticks.insert(key.to_vec(), tick);
+                self.order.insert(tick, key.to_vec());
+            }
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &[u8]) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    /// Removes and returns the least recently used key.
+    pub(crate) fn pop_oldest(&mut self) -> Option<Vec<u8>> {
+        let (_, key) = self.order.pop_first()?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ticks.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_keys_in_least_recently_used_order() {
+        let mut lru = Lru::default();
+        lru.touch(b"a");
+        lru.touch(b"b");
+        lru.touch(b"c");
+        lru.touch(b"a");
+        lru.remove(b"c");
+
+        assert_eq!(Some(b"b".to_vec()), lru.pop_oldest());
+        assert_eq!(Some(b"a".to_vec()), lru.pop_oldest());
+        assert_eq!(None, lru.pop_oldest());
+    }
+}
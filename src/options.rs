@@ -0,0 +1,281 @@
+#[cfg(feature = "serde")]
+use crate::codec::SerdeCodec;
+use crate::{
+    checksum::ChecksumKind,
+    clock::{Clock, SystemClock},
+    compression::Compression,
+    endian::Endianness,
+    event::{EventHandler, StoreEvent},
+};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+/// Value positions are stored as `u32`, so a single file can't grow beyond this.
+const DEFAULT_MAX_FILE_SIZE: usize = u32::MAX as usize;
+
+/// Sizes are stored as `u32`, with `u32::MAX` reserved to mark tombstones.
+const DEFAULT_MAX_KEY_SIZE: usize = u32::MAX as usize;
+const DEFAULT_MAX_VALUE_SIZE: usize = u32::MAX as usize - 1;
+
+const DEFAULT_MERGE_THRESHOLD: f64 = 0.5;
+
+/// How often writes are forced to stable storage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum SyncMode {
+    #[default]
+    Never,
+    Always,
+    EveryN(usize),
+}
+
+/// How much work `DiskStore::open` does to rebuild the keydir. A current
+/// hint file is used in every mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum RecoveryMode {
+    /// Without a hint, reads and checks every entry before returning.
+    #[default]
+    Full,
+    /// Fails with `HintUnavailable` instead of scanning, bounding open time.
+    HintOnly,
+    /// Without a hint, opens straight away. The first lookup indexes the
+    /// file from entry headers and keys, seeking past values, and caches
+    /// every offset; it can't stop at the key it wants, since a later entry
+    /// may supersede it. The first write does the same before appending.
+    Lazy,
+}
+
+/// Whether a store may modify its data directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum OpenMode {
+    #[default]
+    ReadWrite,
+    /// Never creates, locks or writes files, so any number of readers can
+    /// share a directory.
+    ReadOnly,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Options {
+    pub(crate) max_file_size: usize,
+    pub(crate) sync_mode: SyncMode,
+    pub(crate) data_dir: PathBuf,
+    pub(crate) max_key_size: usize,
+    pub(crate) max_value_size: usize,
+    pub(crate) clock: Arc<dyn Clock>,
+    /// Number of keys to size the Bloom filter for, if one is enabled.
+    pub(crate) bloom_filter_keys: Option<usize>,
+    pub(crate) compression: Compression,
+    pub(crate) byte_order: Endianness,
+    pub(crate) checksum: ChecksumKind,
+    pub(crate) open_mode: OpenMode,
+    pub(crate) recovery: RecoveryMode,
+    pub(crate) event_handler: Option<EventHandler>,
+    /// Cap on live keys, beyond which the least recently used are evicted.
+    pub(crate) max_entries: Option<usize>,
+    /// How long `merge` keeps tombstones before dropping them for good.
+    pub(crate) tombstone_ttl: Duration,
+    /// Fragmentation ratio above which `compact_if_needed` merges.
+    pub(crate) merge_threshold: f64,
+    pub(crate) use_mmap: bool,
+    /// Whether disk reads check the entry checksum before returning a value.
+    pub(crate) verify_on_read: bool,
+    /// Bytes of writes to collect before appending them to disk together.
+    pub(crate) write_buffer_size: usize,
+    #[cfg(feature = "serde")]
+    pub(crate) serde_codec: SerdeCodec,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            sync_mode: SyncMode::default(),
+            data_dir: PathBuf::new(),
+            max_key_size: DEFAULT_MAX_KEY_SIZE,
+            max_value_size: DEFAULT_MAX_VALUE_SIZE,
+            clock: Arc::new(SystemClock),
+            bloom_filter_keys: None,
+            compression: Compression::default(),
+            byte_order: Endianness::default(),
+            checksum: ChecksumKind::default(),
+            open_mode: OpenMode::default(),
+            recovery: RecoveryMode::default(),
+            event_handler: None,
+            max_entries: None,
+            tombstone_ttl: Duration::ZERO,
+            merge_threshold: DEFAULT_MERGE_THRESHOLD,
+            use_mmap: false,
+            verify_on_read: true,
+            write_buffer_size: 0,
+            #[cfg(feature = "serde")]
+            serde_codec: SerdeCodec::default(),
+        }
+    }
+}
+
+impl Options {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub(crate) fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.sync_mode = sync_mode;
+        self
+    }
+
+    /// Directory that relative store paths are resolved against.
+    pub(crate) fn data_dir(mut self, data_dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = data_dir.into();
+        self
+    }
+
+    /// Caps key length, never beyond what the on-disk `u32` size can hold.
+    pub(crate) fn max_key_size(mut self, max_key_size: usize) -> Self {
+        self.max_key_size = max_key_size.min(DEFAULT_MAX_KEY_SIZE);
+        self
+    }
+
+    /// Caps value length, never beyond what the on-disk `u32` size can hold.
+    pub(crate) fn max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = max_value_size.min(DEFAULT_MAX_VALUE_SIZE);
+        self
+    }
+
+    pub(crate) fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Turns the store into a bounded cache: once more than `max_entries`
+    /// keys are live, the least recently read or written one is deleted.
+    pub(crate) fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Keeps tombstones younger than `ttl` through merges, so a replica or
+    /// older file can't resurrect a deleted key.
+    pub(crate) fn tombstone_ttl(mut self, ttl: Duration) -> Self {
+        self.tombstone_ttl = ttl;
+        self
+    }
+
+    pub(crate) fn merge_threshold(mut self, merge_threshold: f64) -> Self {
+        self.merge_threshold = merge_threshold;
+        self
+    }
+
+    /// Serves disk reads from a memory map of each data file instead of
+    /// seeking and reading.
+    pub(crate) fn use_mmap(mut self, use_mmap: bool) -> Self {
+        self.use_mmap = use_mmap;
+        self
+    }
+
+    /// Skipping the checksum on reads saves CPU, but corrupted values are
+    /// then returned as they are instead of failing with `ChecksumMismatch`.
+    pub(crate) fn verify_on_read(mut self, verify_on_read: bool) -> Self {
+        self.verify_on_read = verify_on_read;
+        self
+    }
+
+    /// Holds writes in memory until `bytes` have built up, then appends them
+    /// to disk as one. Buffered writes are readable straight away but are
+    /// lost on a crash until flushed; 0 writes everything through.
+    pub(crate) fn write_buffer_size(mut self, bytes: usize) -> Self {
+        self.write_buffer_size = bytes;
+        self
+    }
+
+    /// Format for values stored with `put_serde`.
+    #[cfg(feature = "serde")]
+    pub(crate) fn serde_codec(mut self, codec: SerdeCodec) -> Self {
+        self.serde_codec = codec;
+        self
+    }
+
+    /// Keeps a Bloom filter of written keys so lookups for keys that were
+    /// never stored can skip the keydir.
+    pub(crate) fn enable_bloom_filter(mut self, expected_keys: usize) -> Self {
+        self.bloom_filter_keys = Some(expected_keys);
+        self
+    }
+
+    pub(crate) fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Byte order for new files. Existing files must have been written with
+    /// the same order.
+    pub(crate) fn byte_order(mut self, byte_order: Endianness) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    /// Checksum algorithm for new files. Existing files keep the one their
+    /// header records.
+    pub(crate) fn checksum(mut self, checksum: ChecksumKind) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub(crate) fn open_mode(mut self, open_mode: OpenMode) -> Self {
+        self.open_mode = open_mode;
+        self
+    }
+
+    /// Only `DiskStore` keeps hints; `Store` always scans its files.
+    pub(crate) fn recovery(mut self, recovery: RecoveryMode) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Registers a callback that runs after every successful mutation.
+    pub(crate) fn on_event(mut self, f: Box<dyn Fn(&StoreEvent) + Send + Sync>) -> Self {
+        self.event_handler = Some(EventHandler::new(f));
+        self
+    }
+
+    /// Reports an event to the registered callback. `event` is only built
+    /// when there is one.
+    pub(crate) fn emit(&self, event: impl FnOnce() -> StoreEvent) {
+        if let Some(handler) = &self.event_handler {
+            handler.call(&event());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_builder_overrides_defaults() {
+        let opts = Options::new()
+            .max_file_size(1024)
+            .sync_mode(SyncMode::EveryN(4))
+            .data_dir("/tmp/caska")
+            .max_key_size(16)
+            .max_value_size(usize::MAX);
+
+        assert_eq!(1024, opts.max_file_size);
+        assert_eq!(SyncMode::EveryN(4), opts.sync_mode);
+        assert_eq!(PathBuf::from("/tmp/caska"), opts.data_dir);
+        assert_eq!(16, opts.max_key_size);
+        assert_eq!(DEFAULT_MAX_VALUE_SIZE, opts.max_value_size);
+    }
+
+    #[test]
+    fn default_options_match_unconfigured_store() {
+        let opts = Options::default();
+
+        assert_eq!(DEFAULT_MAX_FILE_SIZE, opts.max_file_size);
+        assert_eq!(SyncMode::Never, opts.sync_mode);
+        assert_eq!(PathBuf::new(), opts.data_dir);
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{error::Result, MemoryStore, MergeReport, ToBytes};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Times [`SharedStore::merge`] rebuilds a merge overtaken by a rotation
+/// before merging under the write lock.
+const MERGE_ATTEMPTS: usize = 3;
+
+/// A cloneable handle to a `MemoryStore` that can be shared across threads.
+/// Reads proceed concurrently while writes are serialized.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedStore {
+    inner: Arc<RwLock<MemoryStore>>,
+}
+
+impl SharedStore {
+    pub(crate) fn new(store: MemoryStore) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(store)),
+        }
+    }
+
+    pub(crate) fn get<K: ToBytes>(&self, key: K) -> Option<Vec<u8>> {
+        self.read().get(key)
+    }
+
+    pub(crate) fn contains_key<K: ToBytes>(&self, key: K) -> bool {
+        self.read().contains_key(key)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub(crate) fn put<K, V>(&self, key: K, value: V) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        self.write().put(key, value)
+    }
+
+    pub(crate) fn delete<K: ToBytes>(&self, key: K) -> Result<()> {
+        self.write().delete(key)
+    }
+
+    /// Merges without stalling readers or writers for long. The merge is
+    /// built from a copy of the store taken under a brief read lock, then
+    /// the write lock is taken just to replay whatever was written
+    /// meanwhile and swap the result in. A rotation or `clear` during the
+    /// build can't be replayed, so the merge is retried a few times before
+    /// falling back to merging under the write lock.
+    pub(crate) fn merge(&self) -> Result<MergeReport> {
+        for _ in 0..MERGE_ATTEMPTS {
+            let source = self.read().merge_source();
+            let mut prepared = source.prepare_merge()?;
+            let mut store = self.write();
+            if store.catch_up_merge(&mut prepared)? {
+                return Ok(store.install_merge(prepared));
+            }
+        }
+        self.write().merge()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, MemoryStore> {
+        self.inner.read().expect("store lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, MemoryStore> {
+        self.inner.write().expect("store lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn shared_store_supports_concurrent_readers_and_a_writer() {
+        let store = SharedStore::new(MemoryStore::new());
+        store.put("counter", 0).unwrap();
+
+        let writer = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for i in 1..=100u32 {
+                    store.put("counter", i).unwrap();
+                    store.put(format!("key_{i}").as_str(), i).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..100 {
+                        let bytes = store.get("counter").unwrap();
+                        let current = u32::from_be_bytes(bytes.try_into().unwrap());
+                        assert!(current >= last);
+                        last = current;
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(101, store.len());
+        assert_eq!(100u32.to_be_bytes().to_vec(), store.get("counter").unwrap());
+        assert!(store.contains_key("key_100"));
+    }
+
+    #[test]
+    fn shared_store_serves_reads_while_merging() {
+        let store = SharedStore::new(MemoryStore::with_options(
+            Options::new().max_file_size(1024),
+        ));
+        for round in 0..5u32 {
+            for i in 0..100u32 {
+                store.put(i, round * 100 + i).unwrap();
+            }
+        }
+
+        let merger = {
+            let store = store.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    store.merge().unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    for i in (0..100u32).cycle().take(2000) {
+                        let want = (400 + i).to_be_bytes().to_vec();
+                        assert_eq!(Some(want), store.get(i));
+                    }
+                })
+            })
+            .collect();
+
+        merger.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(100, store.len());
+        assert!(store.read().files.is_empty());
+    }
+
+    #[test]
+    fn shared_store_merge_keeps_writes_made_while_merging() {
+        let store = SharedStore::new(MemoryStore::new());
+        for i in 0..1000u32 {
+            store.put(i, i).unwrap();
+        }
+        let merging = Arc::new(AtomicBool::new(true));
+
+        // Keeps overwriting every key, and moving one extra key along, until
+        // the merges are done. Returns the last round it finished.
+        let writer = {
+            let store = store.clone();
+            let merging = merging.clone();
+            thread::spawn(move || {
+                let mut round = 0;
+                while merging.load(Ordering::Relaxed) {
+                    round += 1;
+                    for i in 0..1000u32 {
+                        store.put(i, round * 1000 + i).unwrap();
+                    }
+                    store
+                        .delete(format!("extra-{}", round - 1).as_str())
+                        .unwrap();
+                    store.put(format!("extra-{round}").as_str(), round).unwrap();
+                }
+                round
+            })
+        };
+        let reader = {
+            let store = store.clone();
+            let merging = merging.clone();
+            thread::spawn(move || {
+                let mut last = vec![0; 1000];
+                for i in (0..1000u32).cycle() {
+                    if !merging.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let bytes = store.get(i).unwrap();
+                    let value = u32::from_be_bytes(bytes.try_into().unwrap());
+                    assert_eq!(i, value % 1000);
+                    assert!(value >= last[i as usize]);
+                    last[i as usize] = value;
+                }
+            })
+        };
+        for _ in 0..10 {
+            store.merge().unwrap();
+        }
+        merging.store(false, Ordering::Relaxed);
+        let round = writer.join().unwrap();
+        reader.join().unwrap();
+        store.merge().unwrap();
+
+        let check = |store: &MemoryStore| {
+            assert_eq!(1001, store.len());
+            for i in 0..1000u32 {
+                let want = (round * 1000 + i).to_be_bytes().to_vec();
+                assert_eq!(Some(want), store.get(i));
+            }
+            let extra = format!("extra-{round}");
+            assert_eq!(
+                Some(round.to_be_bytes().to_vec()),
+                store.get(extra.as_str())
+            );
+        };
+        let merged = store.read();
+        check(&merged);
+        check(&MemoryStore::from_bytes(merged.file.data.clone()).unwrap());
+    }
+
+    #[test]
+    fn merges_catch_up_on_writes_made_while_building() {
+        let mut store = MemoryStore::new();
+        store.put("hello", "world").unwrap();
+        store.put("first_name", "john").unwrap();
+
+        let mut prepared = store.merge_source().prepare_merge().unwrap();
+        store.put("hello", "there").unwrap();
+        store.delete("first_name").unwrap();
+        store.put("last_name", "smith").unwrap();
+        assert!(store.catch_up_merge(&mut prepared).unwrap());
+        store.install_merge(prepared);
+
+        for store in [
+            &store,
+            &MemoryStore::from_bytes(store.file.data.clone()).unwrap(),
+        ] {
+            assert_eq!(Some(b"there".to_vec()), store.get("hello"));
+            assert_eq!(None, store.get("first_name"));
+            assert_eq!(Some(b"smith".to_vec()), store.get("last_name"));
+        }
+
+        let mut prepared = store.merge_source().prepare_merge().unwrap();
+        store.clear().unwrap();
+        assert!(!store.catch_up_merge(&mut prepared).unwrap());
+    }
+
+    #[test]
+    fn shared_store_handles_see_each_others_deletes() {
+        let store = SharedStore::new(MemoryStore::new());
+        let other = store.clone();
+        store.put("hello", "world").unwrap();
+
+        other.delete("hello").unwrap();
+
+        assert_eq!(None, store.get("hello"));
+    }
+}
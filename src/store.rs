@@ -0,0 +1,891 @@
+use crate::{
+    disk::DataFile,
+    error::{CaskaError, Result},
+    event::StoreEvent,
+    merge_keydir,
+    options::{OpenMode, Options},
+    retained_tombstones, sync_parent_dir, FileHeader, KeyDir, KeyDirEntry, KeyValueEntry,
+    MergeReport, ToBytes,
+};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions, TryLockError},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+const DATA_EXTENSION: &str = "data";
+const META_EXTENSION: &str = "meta";
+/// A merged file while it's still being written.
+const MERGING_EXTENSION: &str = "merging";
+/// A complete merged file that hasn't replaced its inputs yet.
+const MERGE_EXTENSION: &str = "merge";
+const LOCK_FILE: &str = "caska.lock";
+
+/// What an immutable data file's `<file_id>.meta` sidecar records, so
+/// monitoring can judge a file without scanning it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileSummary {
+    pub(crate) file_id: u32,
+    pub(crate) entry_count: u64,
+    /// Encoded size of the entries the keydir still points at.
+    pub(crate) live_bytes: u64,
+    /// CRC32 of the whole data file.
+    pub(crate) checksum: u32,
+}
+
+impl FileSummary {
+    fn new(file_id: u32, entry_count: usize, data: &[u8]) -> Self {
+        Self {
+            file_id,
+            entry_count: entry_count as u64,
+            live_bytes: 0,
+            checksum: crc32fast::hash(data),
+        }
+    }
+
+    /// Big-endian `file_id`, `entry_count`, `live_bytes` and `checksum`.
+    fn encode(self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        buf.write_u32::<BigEndian>(self.file_id)?;
+        buf.write_u64::<BigEndian>(self.entry_count)?;
+        buf.write_u64::<BigEndian>(self.live_bytes)?;
+        buf.write_u32::<BigEndian>(self.checksum)?;
+        Ok(buf)
+    }
+
+    fn decode(mut data: &[u8]) -> Result<Self> {
+        Ok(Self {
+            file_id: data.read_u32::<BigEndian>()?,
+            entry_count: data.read_u64::<BigEndian>()?,
+            live_bytes: data.read_u64::<BigEndian>()?,
+            checksum: data.read_u32::<BigEndian>()?,
+        })
+    }
+}
+
+/// Reads the sidecar of one data file without opening the store.
+pub(crate) fn read_file_summary(dir: impl AsRef<Path>, file_id: u32) -> Result<FileSummary> {
+    FileSummary::decode(&fs::read(meta_file_path(dir.as_ref(), file_id))?)
+}
+
+/// A database spread over numbered `<file_id>.data` files in one directory.
+/// Only the highest-numbered file is written to; the rest are read-only.
+#[derive(Debug)]
+pub(crate) struct Store {
+    dir: PathBuf,
+    active_id: u32,
+    files: HashMap<u32, DataFile>,
+    keydir: KeyDir,
+    /// Sidecar contents for every immutable file.
+    summaries: BTreeMap<u32, FileSummary>,
+    options: Options,
+    /// Sequence number of the most recent write, across every file.
+    seq: u64,
+    /// Holds an exclusive lock on the directory until the store is dropped.
+    /// Read-only stores don't take one.
+    _lock: Option<fs::File>,
+}
+
+impl Store {
+    pub(crate) fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(dir, Options::default())
+    }
+
+    pub(crate) fn open_with(dir: impl AsRef<Path>, options: Options) -> Result<Self> {
+        let dir = options.data_dir.join(dir);
+        let read_only = options.open_mode == OpenMode::ReadOnly;
+        let lock = if read_only {
+            None
+        } else {
+            fs::create_dir_all(&dir)?;
+            let lock = lock_dir(&dir)?;
+            recover_merges(&dir)?;
+            Some(lock)
+        };
+
+        let mut file_ids = data_file_ids(&dir)?;
+        file_ids.sort_unstable();
+
+        let now = options.clock.now();
+        let mut files = HashMap::new();
+        let mut keydir = KeyDir::new();
+        let mut removed = HashMap::new();
+        let mut seq = 0;
+        let mut summaries = BTreeMap::new();
+        for &file_id in &file_ids {
+            let path = data_file_path(&dir, file_id);
+            let mut file = if read_only {
+                DataFile::open_read_only(&path, &options)?
+            } else {
+                DataFile::open(&path, &options)?
+            };
+            let data = file.read_all()?;
+            let recovery = merge_keydir(
+                file_id,
+                &data,
+                options.byte_order,
+                now,
+                &mut keydir,
+                &mut removed,
+            )?;
+            seq = seq.max(recovery.last_seq);
+            let valid_len = data.len() - recovery.bytes_truncated;
+            if recovery.bytes_truncated > 0 && !read_only {
+                file.truncate(valid_len as u64)?;
+                let truncated_bytes = recovery.bytes_truncated;
+                options.emit(|| StoreEvent::Repaired { truncated_bytes });
+            }
+            let summary = FileSummary::new(file_id, recovery.entries_loaded, &data[..valid_len]);
+            summaries.insert(file_id, summary);
+            files.insert(file_id, file);
+        }
+
+        let active_id = match file_ids.last() {
+            Some(&file_id) => file_id,
+            None if read_only => 0,
+            None => {
+                let file = DataFile::open(&data_file_path(&dir, 0), &options)?;
+                files.insert(0, file);
+                0
+            }
+        };
+
+        summaries.remove(&active_id);
+
+        let mut store = Self {
+            dir,
+            active_id,
+            files,
+            keydir,
+            summaries,
+            options,
+            seq,
+            _lock: lock,
+        };
+        if !read_only {
+            store.refresh_summaries()?;
+        }
+        Ok(store)
+    }
+
+    /// Summaries of the immutable files, in file id order. Live bytes are
+    /// counted now; the sidecars only catch up on open, rotation and merge.
+    pub(crate) fn file_summaries(&self) -> Vec<FileSummary> {
+        let mut summaries = self.summaries.clone();
+        count_live_bytes(&self.keydir, &mut summaries);
+        summaries.into_values().collect()
+    }
+
+    /// Compacts every immutable file into one that takes the highest of
+    /// their ids, leaving the active file alone.
+    ///
+    /// The merged file is synced and renamed to `<id>.merge` before any
+    /// input is deleted. From then on `open` finishes an interrupted merge
+    /// rather than discarding it, so tombstones can be dropped without an
+    /// older file ever bringing a deleted value back. Those younger than
+    /// `tombstone_ttl` are kept anyway, for replicas' sake.
+    pub(crate) fn merge(&mut self) -> Result<MergeReport> {
+        self.check_writable()?;
+        let started = Instant::now();
+        let file_ids: Vec<u32> = self.summaries.keys().copied().collect();
+        let Some(&merged_id) = file_ids.last() else {
+            return Ok(MergeReport::default());
+        };
+
+        let now = self.options.clock.now();
+        let template = &self.files[&merged_id];
+        let mut data = template.header().encode();
+        let entries = file_ids
+            .iter()
+            .flat_map(|file_id| self.files[file_id].entry_headers());
+        let tombstones = retained_tombstones(entries, &self.keydir, &self.options, now)?;
+        let tombstone_count = tombstones.len();
+        for tombstone in tombstones.into_values() {
+            data.extend(template.encode(tombstone)?);
+        }
+        let mut copied = vec![];
+        let mut expired = vec![];
+        for (key, keydir_entry) in &self.keydir {
+            if keydir_entry.file_id == self.active_id {
+                continue;
+            }
+            if keydir_entry.is_expired(now) {
+                expired.push(key.clone());
+                continue;
+            }
+            let stored = self.files[&keydir_entry.file_id].read_stored(keydir_entry)?;
+            let entry = KeyValueEntry::restore(key.clone(), keydir_entry, stored);
+            copied.push((key.clone(), KeyDirEntry::new(merged_id, data.len(), &entry)));
+            data.extend(template.encode(entry)?);
+        }
+
+        let merging_path = self.dir.join(format!("{merged_id}.{MERGING_EXTENSION}"));
+        let mut merging = fs::File::create(&merging_path)?;
+        merging.write_all(&data)?;
+        merging.sync_all()?;
+        let merge_path = merge_file_path(&self.dir, merged_id);
+        fs::rename(&merging_path, &merge_path)?;
+        // The merge is committed once the rename itself is durable.
+        sync_parent_dir(&merge_path)?;
+
+        let entries_before = self
+            .summaries
+            .values()
+            .map(|s| s.entry_count as usize)
+            .sum();
+        let bytes_before: usize = file_ids
+            .iter()
+            .map(|file_id| self.files[file_id].size() as usize)
+            .sum();
+        for file_id in &file_ids {
+            self.files.remove(file_id);
+        }
+        finish_merge(&self.dir, merged_id)?;
+
+        let file = DataFile::open(&data_file_path(&self.dir, merged_id), &self.options)?;
+        self.files.insert(merged_id, file);
+        for key in expired {
+            self.keydir.remove(&key);
+        }
+        let entries_after = copied.len() + tombstone_count;
+        self.keydir.extend(copied);
+        self.summaries.clear();
+        self.summaries
+            .insert(merged_id, FileSummary::new(merged_id, entries_after, &data));
+        self.refresh_summaries()?;
+
+        let bytes_after = data.len();
+        let reclaimed_bytes = bytes_before.saturating_sub(bytes_after);
+        self.options.emit(|| StoreEvent::Merge { reclaimed_bytes });
+
+        Ok(MergeReport {
+            files_merged: file_ids.len(),
+            entries_before,
+            entries_after,
+            bytes_before,
+            bytes_after,
+            reclaimed_bytes,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    pub(crate) fn get<K: ToBytes>(&self, key: K) -> Result<Option<Vec<u8>>> {
+        let key = key.to_bytes();
+        let Some(entry) = self.keydir.get(&key) else {
+            return Ok(None);
+        };
+        let file = self.files.get(&entry.file_id).ok_or_else(|| {
+            CaskaError::InvalidEntry(format!("data file {} is missing", entry.file_id))
+        })?;
+        file.read_value(&key, entry).map(Some)
+    }
+
+    pub(crate) fn put<K, V>(&mut self, key: K, value: V) -> Result<()>
+    where
+        K: ToBytes,
+        V: ToBytes,
+    {
+        self.check_writable()?;
+        let entry =
+            KeyValueEntry::new(self.options.clock.now(), key, value).with_seq(self.next_seq());
+        entry.check_size(&self.options)?;
+        let entry = entry.compress(self.options.compression)?;
+        self.rotate_if_needed(entry.size())?;
+
+        let keydir_entry = KeyDirEntry::new(self.active_id, self.active().size() as usize, &entry);
+        let key = entry.key.clone();
+        let entry_data = self.active().encode(entry)?;
+        self.append(&entry_data)?;
+        let value_sz = keydir_entry.value_sz;
+        self.keydir.insert(key.clone(), keydir_entry);
+        self.options.emit(|| StoreEvent::Put { key, value_sz });
+
+        Ok(())
+    }
+
+    pub(crate) fn delete<K: ToBytes>(&mut self, key: K) -> Result<()> {
+        self.check_writable()?;
+        let entry =
+            KeyValueEntry::tombstone(self.options.clock.now(), key).with_seq(self.next_seq());
+        entry.check_size(&self.options)?;
+        self.rotate_if_needed(entry.size())?;
+
+        let key = entry.key.clone();
+        let entry_data = self.active().encode(entry)?;
+        self.append(&entry_data)?;
+        self.keydir.remove(&key);
+        self.options.emit(|| StoreEvent::Delete { key });
+
+        Ok(())
+    }
+
+    /// Hands buffered writes to the OS, so other handles can read them. Cheap
+    /// next to [`Store::sync`], but not durable across a crash.
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.active_mut().flush()
+    }
+
+    /// Flushes, then fsyncs the active file.
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.active_mut().sync()
+    }
+
+    /// Shuts down cleanly: syncs buffered writes, brings the sidecars up to
+    /// date and releases the lock. Dropping also flushes, but can't report
+    /// errors and doesn't sync.
+    pub(crate) fn close(mut self) -> Result<()> {
+        if self.options.open_mode == OpenMode::ReadOnly {
+            return Ok(());
+        }
+        self.active_mut().sync()?;
+        self.refresh_summaries()
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    /// Recounts live bytes and rewrites every sidecar.
+    fn refresh_summaries(&mut self) -> Result<()> {
+        count_live_bytes(&self.keydir, &mut self.summaries);
+        for summary in self.summaries.values() {
+            fs::write(
+                meta_file_path(&self.dir, summary.file_id),
+                summary.encode()?,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        match self.options.open_mode {
+            OpenMode::ReadWrite => Ok(()),
+            OpenMode::ReadOnly => Err(CaskaError::ReadOnly),
+        }
+    }
+
+    fn active(&self) -> &DataFile {
+        &self.files[&self.active_id]
+    }
+
+    fn active_mut(&mut self) -> &mut DataFile {
+        self.files
+            .get_mut(&self.active_id)
+            .expect("active data file is always open")
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let sync_mode = self.options.sync_mode;
+        self.active_mut().append(data, sync_mode)
+    }
+
+    /// Starts a new numbered file when `entry_size` more bytes would push
+    /// the active one past `max_file_size`.
+    fn rotate_if_needed(&mut self, entry_size: usize) -> Result<()> {
+        let size = self.active().size() as usize;
+        if size <= FileHeader::SIZE || size + entry_size <= self.options.max_file_size {
+            return Ok(());
+        }
+
+        // Flush the outgoing file so it's complete before it becomes read-only.
+        self.active_mut().sync()?;
+        let active_id = self.active_id;
+        let outgoing = self.active_mut().snapshot(active_id)?;
+        let summary = FileSummary::new(outgoing.id, outgoing.entry_count(), &outgoing.data);
+        self.summaries.insert(outgoing.id, summary);
+
+        self.active_id += 1;
+        let path = data_file_path(&self.dir, self.active_id);
+        let file = DataFile::open(&path, &self.options)?;
+        self.files.insert(self.active_id, file);
+
+        self.refresh_summaries()
+    }
+}
+
+/// Takes the directory's lock file so a second handle can't write to the
+/// same data files.
+fn lock_dir(dir: &Path) -> Result<fs::File> {
+    let lock = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(dir.join(LOCK_FILE))?;
+    match lock.try_lock() {
+        Ok(()) => Ok(lock),
+        Err(TryLockError::WouldBlock) => Err(CaskaError::AlreadyLocked),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+fn count_live_bytes(keydir: &KeyDir, summaries: &mut BTreeMap<u32, FileSummary>) {
+    for summary in summaries.values_mut() {
+        summary.live_bytes = 0;
+    }
+    for (key, entry) in keydir {
+        if let Some(summary) = summaries.get_mut(&entry.file_id) {
+            summary.live_bytes += entry.encoded_size(key) as u64;
+        }
+    }
+}
+
+fn data_file_path(dir: &Path, file_id: u32) -> PathBuf {
+    dir.join(format!("{file_id}.{DATA_EXTENSION}"))
+}
+
+fn meta_file_path(dir: &Path, file_id: u32) -> PathBuf {
+    dir.join(format!("{file_id}.{META_EXTENSION}"))
+}
+
+fn merge_file_path(dir: &Path, file_id: u32) -> PathBuf {
+    dir.join(format!("{file_id}.{MERGE_EXTENSION}"))
+}
+
+/// Replaces every data file up to `merged_id` with the merged file that
+/// covers them. Safe to repeat if interrupted.
+fn finish_merge(dir: &Path, merged_id: u32) -> Result<()> {
+    for file_id in data_file_ids(dir)? {
+        if file_id <= merged_id {
+            fs::remove_file(data_file_path(dir, file_id))?;
+            let meta_path = meta_file_path(dir, file_id);
+            if meta_path.exists() {
+                fs::remove_file(meta_path)?;
+            }
+        }
+    }
+    let data_path = data_file_path(dir, merged_id);
+    fs::rename(merge_file_path(dir, merged_id), &data_path)?;
+    sync_parent_dir(&data_path)
+}
+
+/// Deals with merges cut short by a crash: half-written output is thrown
+/// away, and a complete merged file finishes replacing its inputs.
+fn recover_merges(dir: &Path) -> Result<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let file_id = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u32>().ok());
+        let Some(file_id) = file_id else {
+            continue;
+        };
+        if path.extension().is_some_and(|ext| ext == MERGING_EXTENSION) {
+            fs::remove_file(&path)?;
+        } else if path.extension().is_some_and(|ext| ext == MERGE_EXTENSION) {
+            finish_merge(dir, file_id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ids of every `<file_id>.data` file in `dir`; other files are ignored.
+fn data_file_ids(dir: &Path) -> Result<Vec<u32>> {
+    let mut file_ids = vec![];
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        if path.extension().is_some_and(|ext| ext == DATA_EXTENSION) {
+            if let Some(file_id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                file_ids.push(file_id);
+            }
+        }
+    }
+    Ok(file_ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        checksum::ChecksumKind,
+        clock::MockClock,
+        endian::Endianness,
+        testing::{append_torn_entry, valid_stream},
+        FORMAT_VERSION,
+    };
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    fn write_data_file(dir: &Path, file_id: u32, entries: Vec<KeyValueEntry>) {
+        let mut data = FileHeader::new(Endianness::Big, ChecksumKind::Crc32).encode();
+        for entry in entries {
+            data.extend(Vec::try_from(entry).unwrap());
+        }
+        fs::write(data_file_path(dir, file_id), data).unwrap();
+    }
+
+    #[test]
+    fn store_open_merges_keydirs_from_every_data_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write_data_file(
+            dir.path(),
+            1,
+            vec![
+                KeyValueEntry::new(1, "hello", "world"),
+                KeyValueEntry::new(1, "first_name", "john"),
+                KeyValueEntry::new(3, "last_name", "smith"),
+            ],
+        );
+        write_data_file(
+            dir.path(),
+            2,
+            vec![
+                KeyValueEntry::new(2, "hello", "there"),
+                KeyValueEntry::tombstone(2, "first_name"),
+                KeyValueEntry::new(2, "last_name", "jones"),
+            ],
+        );
+
+        let store = Store::open(dir.path()).unwrap();
+
+        assert_eq!(2, store.active_id);
+        assert_eq!(Some(b"there".to_vec()), store.get("hello").unwrap());
+        assert_eq!(None, store.get("first_name").unwrap());
+        assert_eq!(Some(b"smith".to_vec()), store.get("last_name").unwrap());
+        assert_eq!(1, store.keydir[b"last_name".as_slice()].file_id);
+    }
+
+    #[test]
+    fn store_open_breaks_timestamp_ties_by_sequence_number() {
+        let dir = tempfile::tempdir().unwrap();
+        write_data_file(
+            dir.path(),
+            1,
+            vec![KeyValueEntry::new(5, "hello", "newer").with_seq(2)],
+        );
+        write_data_file(
+            dir.path(),
+            2,
+            vec![KeyValueEntry::new(5, "hello", "older").with_seq(1)],
+        );
+
+        let mut store = Store::open(dir.path()).unwrap();
+        assert_eq!(Some(b"newer".to_vec()), store.get("hello").unwrap());
+
+        store.put("hello", "newest").unwrap();
+        assert_eq!(3, store.keydir[b"hello".as_slice()].seq);
+    }
+
+    #[test]
+    fn store_open_rejects_files_from_the_previous_format_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(dir.path()).unwrap();
+        store.put("hello", "world").unwrap();
+        drop(store);
+
+        let path = data_file_path(dir.path(), 0);
+        let mut data = fs::read(&path).unwrap();
+        data[4..6].copy_from_slice(&(FORMAT_VERSION - 1).to_be_bytes());
+        fs::write(&path, data).unwrap();
+
+        let err = Store::open(dir.path()).unwrap_err();
+        assert!(matches!(
+            err,
+            CaskaError::UnsupportedVersion { found, expected: FORMAT_VERSION }
+                if found == FORMAT_VERSION - 1
+        ));
+    }
+
+    #[test]
+    fn store_rotates_into_new_numbered_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().max_file_size(64);
+
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        for i in 0..10u32 {
+            store.put(i, i).unwrap();
+        }
+        assert!(store.active_id > 0);
+        assert!(data_file_path(dir.path(), store.active_id).exists());
+        drop(store);
+
+        let store = Store::open_with(dir.path(), options).unwrap();
+        for i in 0..10u32 {
+            assert_eq!(Some(i.to_bytes()), store.get(i).unwrap());
+        }
+    }
+
+    #[test]
+    fn store_drop_flushes_buffered_writes_and_close_releases_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().write_buffer_size(1024);
+
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        store.put("hello", "world").unwrap();
+        drop(store);
+
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        assert_eq!(Some(b"world".to_vec()), store.get("hello").unwrap());
+        store.put("first_name", "john").unwrap();
+        // `close` takes the store by value, so it can't be closed twice.
+        store.close().unwrap();
+
+        let store = Store::open_with(dir.path(), options).unwrap();
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+    }
+
+    #[test]
+    fn store_flush_makes_buffered_writes_visible_to_a_fresh_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().write_buffer_size(1024);
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        store.put("hello", "world").unwrap();
+
+        let read_only = options.open_mode(OpenMode::ReadOnly);
+        let reader = Store::open_with(dir.path(), read_only.clone()).unwrap();
+        assert_eq!(None, reader.get("hello").unwrap());
+
+        store.flush().unwrap();
+        let reader = Store::open_with(dir.path(), read_only).unwrap();
+        assert_eq!(Some(b"world".to_vec()), reader.get("hello").unwrap());
+    }
+
+    /// Options that record every event into the returned list.
+    fn recording_options() -> (Options, Arc<Mutex<Vec<StoreEvent>>>) {
+        let events = Arc::new(Mutex::new(vec![]));
+        let recorded = events.clone();
+        let options = Options::new().on_event(Box::new(move |event| {
+            recorded.lock().unwrap().push(event.clone());
+        }));
+        (options, events)
+    }
+
+    #[test]
+    fn store_open_leaves_a_clean_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = valid_stream(&[("hello", "world"), ("first_name", "john")]);
+        fs::write(data_file_path(dir.path(), 0), &data).unwrap();
+
+        let (options, events) = recording_options();
+        let store = Store::open_with(dir.path(), options).unwrap();
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+        drop(store);
+        assert_eq!(data, fs::read(data_file_path(dir.path(), 0)).unwrap());
+    }
+
+    #[test]
+    fn store_open_repairs_a_torn_tail_unless_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let valid = valid_stream(&[("hello", "world"), ("first_name", "john")]);
+        let mut data = valid.clone();
+        let torn = append_torn_entry(&mut data);
+        let path = data_file_path(dir.path(), 0);
+        fs::write(&path, &data).unwrap();
+
+        let (options, events) = recording_options();
+        let reader = Store::open_with(dir.path(), options.clone().open_mode(OpenMode::ReadOnly));
+        assert_eq!(
+            Some(b"world".to_vec()),
+            reader.unwrap().get("hello").unwrap()
+        );
+        assert!(events.lock().unwrap().is_empty());
+        assert_eq!(data, fs::read(&path).unwrap());
+
+        let store = Store::open_with(dir.path(), options).unwrap();
+        let want = vec![StoreEvent::Repaired {
+            truncated_bytes: torn,
+        }];
+        assert_eq!(want, *events.lock().unwrap());
+        assert_eq!(valid, fs::read(&path).unwrap());
+        assert_eq!(Some(b"john".to_vec()), store.get("first_name").unwrap());
+        assert_eq!(None, store.get("torn").unwrap());
+    }
+
+    #[test]
+    fn store_open_fails_while_another_handle_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = Store::open(dir.path()).unwrap();
+        let err = Store::open(dir.path()).unwrap_err();
+        assert!(matches!(err, CaskaError::AlreadyLocked));
+
+        drop(store);
+        assert!(Store::open(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn read_only_store_can_get_but_not_put() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = Store::open(dir.path()).unwrap();
+        store.put("hello", "world").unwrap();
+        let files_before = fs::read_dir(dir.path()).unwrap().count();
+
+        let options = Options::new().open_mode(OpenMode::ReadOnly);
+        let mut reader = Store::open_with(dir.path(), options.clone()).unwrap();
+        let other_reader = Store::open_with(dir.path(), options).unwrap();
+
+        assert_eq!(Some(b"world".to_vec()), reader.get("hello").unwrap());
+        assert_eq!(Some(b"world".to_vec()), other_reader.get("hello").unwrap());
+        assert!(matches!(
+            reader.put("hello", "there").unwrap_err(),
+            CaskaError::ReadOnly
+        ));
+        assert!(matches!(
+            reader.delete("hello").unwrap_err(),
+            CaskaError::ReadOnly
+        ));
+        assert_eq!(files_before, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn read_only_store_does_not_create_missing_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+
+        let options = Options::new().open_mode(OpenMode::ReadOnly);
+        assert!(Store::open_with(&path, options).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn store_ignores_unrelated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+        fs::write(dir.path().join("backup.data"), "hello").unwrap();
+
+        let mut store = Store::open(dir.path()).unwrap();
+        store.put("hello", "world").unwrap();
+        store.delete("hello").unwrap();
+
+        assert_eq!(None, store.get("hello").unwrap());
+        assert!(data_file_path(dir.path(), 0).exists());
+    }
+
+    fn scan_summary(store: &Store, file_id: u32) -> FileSummary {
+        let path = data_file_path(&store.dir, file_id);
+        let file = DataFile::open_read_only(&path, &store.options)
+            .unwrap()
+            .snapshot(file_id)
+            .unwrap();
+        let live_bytes = store
+            .keydir
+            .iter()
+            .filter(|(_, entry)| entry.file_id == file_id)
+            .map(|(key, entry)| entry.encoded_size(key) as u64)
+            .sum();
+        FileSummary {
+            live_bytes,
+            ..FileSummary::new(file_id, file.entry_count(), &file.data)
+        }
+    }
+
+    #[test]
+    fn file_summaries_match_a_full_scan_and_update_after_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options::new().max_file_size(128);
+
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        for round in 0..3u32 {
+            for i in 0..5u32 {
+                store.put(i, i + round).unwrap();
+            }
+        }
+        store.delete(0u32).unwrap();
+
+        let summaries = store.file_summaries();
+        assert!(summaries.len() > 1);
+        for summary in &summaries {
+            assert_eq!(scan_summary(&store, summary.file_id), *summary);
+            let sidecar = read_file_summary(dir.path(), summary.file_id).unwrap();
+            assert_eq!(summary.entry_count, sidecar.entry_count);
+            assert_eq!(summary.checksum, sidecar.checksum);
+        }
+
+        let old_ids: Vec<u32> = summaries.iter().map(|s| s.file_id).collect();
+        let report = store.merge().unwrap();
+        assert_eq!(old_ids.len(), report.files_merged);
+        assert!(report.reclaimed_bytes > 0);
+
+        let merged_id = *old_ids.last().unwrap();
+        let summaries = store.file_summaries();
+        assert_eq!(vec![scan_summary(&store, merged_id)], summaries);
+        assert_eq!(
+            summaries[0],
+            read_file_summary(dir.path(), merged_id).unwrap()
+        );
+        for file_id in &old_ids[..old_ids.len() - 1] {
+            assert!(!data_file_path(dir.path(), *file_id).exists());
+            assert!(!meta_file_path(dir.path(), *file_id).exists());
+        }
+        drop(store);
+
+        let store = Store::open_with(dir.path(), options).unwrap();
+        assert_eq!(None, store.get(0u32).unwrap());
+        for i in 1..5u32 {
+            assert_eq!(Some((i + 2).to_bytes()), store.get(i).unwrap());
+        }
+    }
+
+    #[test]
+    fn store_merge_keeps_recent_tombstones_across_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = Arc::new(MockClock::new(1_700_000_000));
+        let options = Options::new()
+            .max_file_size(64)
+            .clock(clock.clone())
+            .tombstone_ttl(Duration::from_secs(60));
+        let mut store = Store::open_with(dir.path(), options.clone()).unwrap();
+        store.put("old", "value").unwrap();
+        store.delete("old").unwrap();
+        clock.advance(120);
+        store.put("fresh", "value").unwrap();
+        store.delete("fresh").unwrap();
+        for i in 0..4u32 {
+            store.put(i, i).unwrap();
+        }
+        assert!(store.summaries.len() > 1);
+
+        store.merge().unwrap();
+
+        let merged_id = *store.summaries.keys().next().unwrap();
+        let tombstones: Vec<_> = store.files[&merged_id]
+            .entry_headers()
+            .map(Result::unwrap)
+            .filter(KeyValueEntry::is_tombstone)
+            .map(|entry| (entry.key, entry.tstamp))
+            .collect();
+        assert_eq!(vec![(b"fresh".to_vec(), 1_700_000_120)], tombstones);
+        drop(store);
+
+        let store = Store::open_with(dir.path(), options).unwrap();
+        assert_eq!(None, store.get("old").unwrap());
+        assert_eq!(None, store.get("fresh").unwrap());
+        assert_eq!(Some(0u32.to_bytes()), store.get(0u32).unwrap());
+    }
+
+    #[test]
+    fn store_open_finishes_a_committed_merge_and_drops_a_partial_one() {
+        let dir = tempfile::tempdir().unwrap();
+        write_data_file(dir.path(), 0, vec![KeyValueEntry::new(1, "hello", "world")]);
+        write_data_file(dir.path(), 1, vec![KeyValueEntry::new(2, "jane", "doe")]);
+        fs::rename(
+            data_file_path(dir.path(), 1),
+            merge_file_path(dir.path(), 1),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join(format!("3.{MERGING_EXTENSION}")),
+            b"partial",
+        )
+        .unwrap();
+
+        let store = Store::open(dir.path()).unwrap();
+        assert_eq!(None, store.get("hello").unwrap());
+        assert_eq!(Some(b"doe".to_vec()), store.get("jane").unwrap());
+        assert!(!data_file_path(dir.path(), 0).exists());
+        assert!(!dir.path().join(format!("3.{MERGING_EXTENSION}")).exists());
+    }
+}
@@ -0,0 +1,39 @@
+//! Builders for data files in the states a crash can leave them in, and a
+//! per-thread count of the values stores read back.
+
+use crate::{checksum::ChecksumKind, endian::Endianness, FileHeader, KeyValueEntry};
+use std::cell::Cell;
+
+thread_local! {
+    static VALUES_READ: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A complete data file holding `pairs` in write order.
+pub(crate) fn valid_stream(pairs: &[(&str, &str)]) -> Vec<u8> {
+    let mut data = FileHeader::new(Endianness::default(), ChecksumKind::default()).encode();
+    for (seq, (key, value)) in (1..).zip(pairs) {
+        let entry = KeyValueEntry::new(0, *key, *value).with_seq(seq);
+        data.extend(Vec::try_from(entry).unwrap());
+    }
+    data
+}
+
+/// Appends the front half of an entry, as if power was lost mid-write,
+/// returning how many bytes were added.
+pub(crate) fn append_torn_entry(data: &mut Vec<u8>) -> usize {
+    let entry = Vec::try_from(KeyValueEntry::new(0, "torn", "never finished")).unwrap();
+    let torn = &entry[..entry.len() / 2];
+    data.extend_from_slice(torn);
+    torn.len()
+}
+
+/// Notes a value fetched from a data file on this thread, so tests can tell
+/// lazy reads from eager ones.
+pub(crate) fn count_value_read() {
+    VALUES_READ.with(|count| count.set(count.get() + 1));
+}
+
+/// Values fetched on this thread so far.
+pub(crate) fn values_read() -> usize {
+    VALUES_READ.with(Cell::get)
+}